@@ -1,9 +1,33 @@
-use clap::{arg, command, Arg, ArgAction, ArgMatches, Command};
+use clap::parser::ValueSource;
+use clap::{arg, command, crate_name, Arg, ArgAction, ArgMatches, Command};
 use config::Config;
 use config::FileFormat;
-use kla::{Error, KlaClient, KlaClientBuilder, KlaRequestBuilder, OptionalFile, TemplateBuilder};
+use futures::{stream, StreamExt};
+use kla::{
+    ConfigFile, Curl, Environment, Error, Expectations, HttpFile, HurlFile, KlaClient,
+    KlaClientBuilder, KlaRequestBuilder, OpenApi, OptionalFile, Outcome, RequestTemplate, Schema,
+    Snapshot, Template, TemplateBuilder, TestReport, TestResult,
+};
+use log::LevelFilter;
 use regex::Regex;
-use reqwest::ClientBuilder;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, USER_AGENT},
+    Client, ClientBuilder, RequestBuilder,
+};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Instant;
+use tera::Context;
+
+// the exit code when an --expect, --expect-status, --expect-header or hurl assert fails
+const EXIT_ASSERTION_FAILED: i32 = 3;
+// the exit code when --fail is set and the response is not a 2xx, the same as curl --fail
+const EXIT_STATUS_FAILED: i32 = 22;
+// the exit code when --validate finds the request or response does not match the document
+const EXIT_SPEC_VIOLATION: i32 = 4;
+// shown in place of the oauth2 access token when the request is not sent
+const ACCESS_TOKEN_PLACEHOLDER: &str = "<oauth2 access token>";
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -12,12 +36,124 @@ async fn main() -> Result<(), Error> {
         .add_source(OptionalFile::new("/etc/kla/config.toml", FileFormat::Toml))
         .build()?;
 
-    let m = command!()
+    let m = cli().get_matches();
+
+    // run and op can be given -v after the name of the template or operation, so they set up
+    // the logger themselves once those flags are merged in
+    if !matches!(m.subcommand_name(), Some("run" | "op")) {
+        logger(m.get_count("verbose"));
+    }
+
+    let result = match m.subcommand() {
+        Some(("environments", envs)) => run_environments(envs, &conf),
+        Some(("file", file)) => run_file(&m, file, &conf).await,
+        Some(("hurl", hurl)) => run_hurl(&m, hurl, &conf).await,
+        Some(("test", test)) => run_test(&m, test, &conf).await,
+        Some(("workflow", workflow)) => run_workflow(&m, workflow, &conf).await,
+        Some(("run", run)) => run_run(&m, run, &conf).await,
+        Some(("op", op)) => run_op(&m, op, &conf).await,
+        Some(("login", login)) => run_login(&m, login, &conf).await,
+        Some(("import", import)) => match import.subcommand() {
+            Some(("curl", curl)) => run_import_curl(&m, curl, &conf).await,
+            Some(("openapi", openapi)) => run_import_openapi(&m, openapi, &conf),
+            _ => unreachable!("import requires a subcommand"),
+        },
+        _ => run_root(&m, &conf).await,
+    };
+
+    // failed expectations are reported as they are, rather than as an error, and exit with
+    // their own code so scripts can tell them apart from kla failing to make the request.
+    match &result {
+        Err(Error::AssertionFailed(msg)) => {
+            eprintln!("{msg}");
+            std::process::exit(EXIT_ASSERTION_FAILED);
+        }
+        Err(Error::StatusFailed(msg)) => {
+            eprintln!("{msg}");
+            std::process::exit(EXIT_STATUS_FAILED);
+        }
+        Err(Error::SpecViolation(msg)) => {
+            eprintln!("{msg}");
+            std::process::exit(EXIT_SPEC_VIOLATION);
+        }
+        _ => result,
+    }
+}
+
+// cli builds the command line of kla, the flags on the root are shared by every subcommand
+fn cli() -> Command {
+    command!()
         .subcommand_required(false)
         .subcommand(
             new_run()
             .allow_external_subcommands(true)
         )
+        .subcommand(
+            Command::new("op")
+            .about("Call an operation from the environment's OpenAPI document by its operationId, or list them when no id is given")
+            .arg(arg!(-e --env <ENVIRONMENT> "The environment we will run the request against").required(false))
+            .arg(arg!(--var <VAR> "Specify a variable key=value that can be used in the request").action(ArgAction::Append))
+            .allow_external_subcommands(true)
+        )
+        .subcommand(
+            Command::new("import")
+            .about("Import requests from other tools")
+            .subcommand_required(true)
+            .subcommand(
+                Command::new("curl")
+                .about("Run a curl command through kla, or save it as a template with --name")
+                .arg(arg!(-e --env <ENVIRONMENT> "The environment to run against, or save the template into").required(false))
+                .arg(arg!(-n --name <NAME> "Save the request as a template with this name rather than running it"))
+                .arg(arg!(-c --config <FILE> "The config file to save the template into").default_value("config.toml"))
+                .arg(Arg::new("command").help("The curl command, either quoted or as separate arguments").required(true).num_args(1..).trailing_var_arg(true).allow_hyphen_values(true))
+            )
+            .subcommand(
+                Command::new("openapi")
+                .about("Save a template for every operation in an OpenAPI document, and an environment for each of its servers")
+                .arg(arg!(<FILE> "The OpenAPI document, in YAML or JSON").id("file"))
+                .arg(arg!(-e --env <ENVIRONMENT> "The environment to save the templates into").required(false))
+                .arg(arg!(-c --config <FILE> "The config file to save the templates into").default_value("config.toml"))
+            )
+        )
+        .subcommand(
+            Command::new("file")
+            .about("Run the requests in a .http file (VS Code REST Client / JetBrains format)")
+            .arg(arg!(<FILE> "The .http file to run").id("file"))
+            .arg(arg!(-n --name <NAME> "Only run the request with this name, set with `# @name <NAME>`"))
+            .arg(arg!(-e --env <ENVIRONMENT> "The environment supplying the base url").required(false))
+            .arg(arg!(--var <VAR> "Specify a variable key=value that can be used in the requests").action(ArgAction::Append))
+        )
+        .subcommand(
+            Command::new("hurl")
+            .about("Run a Hurl scenario, capturing values between the requests and checking the asserts")
+            .arg(arg!(<FILE> "The .hurl file to run").id("file"))
+            .arg(arg!(-e --env <ENVIRONMENT> "The environment supplying the base url").required(false))
+            .arg(arg!(--var <VAR> "Specify a variable key=value that can be used in the requests").action(ArgAction::Append))
+        )
+        .subcommand(
+            Command::new("test")
+            .about("Run every .hurl file in a directory, reporting which passed and which failed")
+            .arg(arg!(<PATH> "A .hurl file, or a directory to search for them").id("path"))
+            .arg(arg!(-e --env <ENVIRONMENT> "The environment supplying the base url").required(false))
+            .arg(arg!(--var <VAR> "Specify a variable key=value that can be used in the tests").action(ArgAction::Append))
+            .arg(arg!(-j --jobs <JOBS> "The number of files to run at the same time").value_parser(clap::value_parser!(usize)).default_value("1"))
+            .arg(arg!(--junit <FILE> "Write a JUnit XML report into the file"))
+            .arg(arg!(--tap <FILE> "Write a TAP report into the file"))
+        )
+        .subcommand(
+            Command::new("workflow")
+            .about("Run a workflow defined for the environment, or list them when no name is given")
+            .alias("flow")
+            .arg(arg!([NAME] "The workflow to run").id("name"))
+            .arg(arg!(-e --env <ENVIRONMENT> "The environment we will run the workflow against").required(false))
+            .arg(arg!(--var <VAR> "Specify a variable key=value that can be used in the workflow").action(ArgAction::Append))
+        )
+        .subcommand(
+            Command::new("login")
+            .about("Log in to an environment with OAuth2 and PKCE, keeping the tokens for later requests")
+            .arg(arg!(-e --env <ENVIRONMENT> "The environment to log in to").required(false))
+            .arg(arg!(--"no-browser" "Only print the url to log in with, rather than opening it").action(ArgAction::SetTrue))
+        )
         .subcommand(
             Command::new("environments")
             .about("Show the environments that are available to you.")
@@ -27,36 +163,117 @@ async fn main() -> Result<(), Error> {
         .arg(arg!(--agent <AGENT> "The header agent string").default_value("TODO: make it good"))
         .arg(arg!(-e --env <ENVIRONMENT> "The environment we will run the request against").required(false))
         .arg(arg!(-t --template <TEMPLATE> "The template to use when formating the output. prepending with @ will read a file."))
-        .arg(arg!(-t --failure-template <TEMPLATE> "The template to use when formating the failure output. prepending with @ will read a file."))
+        .arg(arg!(--"failure-template" <TEMPLATE> "The template to use when formating the failure output. prepending with @ will read a file."))
         .arg(arg!(-o --output <FILE> "The file to write the output into"))
         .arg(arg!(--timeout <SECONDS> "The amount of time allotted for the request to finish"))
-        .arg(arg!(--basic-auth <BASIC_AUTH> "The username and password seperated by :, a preceding @ denotes a file path."))
-        .arg(arg!(--bearer-token <BEARER_TOKEN> "The bearer token to use in requests. A preceding @ denotes a file path."))
+        .arg(arg!(--"basic-auth" <BASIC_AUTH> "The username and password seperated by :, a preceding @ denotes a file path."))
+        .arg(arg!(--"bearer-token" <BEARER_TOKEN> "The bearer token to use in requests. A preceding @ denotes a file path."))
+        .arg(arg!(--"digest-auth" <DIGEST_AUTH> "The username and password seperated by :, used to answer a Digest challenge from the server.").conflicts_with_all(["basic-auth", "bearer-token"]))
+        .arg(arg!(--"aws-sigv4" <SERVICE_REGION> "Sign the request with AWS Signature Version 4 for the service and region seperated by : (eg --aws-sigv4 execute-api:us-east-1)"))
         .arg(arg!(-H --header <HEADER> "Specify a header The key and value should be seperated by a : (eg --header \"Content-Type: application/json\")").action(ArgAction::Append))
         .arg(arg!(-Q --query <QUERY> "Specify a query parameter The key and value should be seperated by a = (eg --query \"username=Jed\")").action(ArgAction::Append))
         .arg(arg!(-F --form <FORM> "Specify a form key=value to be passed in the form body").action(ArgAction::Append))
-        .arg(arg!(-v --verbose "make it loud and proud").action(ArgAction::SetTrue))
+        .arg(arg!(--var <VAR> "Specify a variable key=value that can be used in the uri, headers, query, form and body (eg --var \"id=42\" with /users/{{ id }})").action(ArgAction::Append))
+        .arg(arg!(--raw "Send the arguments, headers, query and form values given on the command line as they are, rather than rendering them as templates").action(ArgAction::SetTrue))
+        .arg(arg!(-v --verbose "make it loud and proud, print the request and response headers to stderr. Repeat for connection details (-vv) and raw traffic (-vvv)").action(ArgAction::Count))
         .arg(arg!(--dry "don't actually do anything, will automatically enable verbose").action(ArgAction::SetTrue))
-        .arg(arg!(--http-version <HTTP_VERSION> "The version of http to send the request as").value_parser(["0.9", "1.0", "1.1", "2.0", "3.0"]))
-        .arg(arg!(--no-gzip "Do not automatically uncompress gzip responses").action(ArgAction::SetTrue))
-        .arg(arg!(--no-brotli "Do not automatically uncompress brotli responses").action(ArgAction::SetTrue))
-        .arg(arg!(--no-deflate "Do not automatically uncompress deflate responses").action(ArgAction::SetTrue))
-        .arg(arg!(--max-redirects <NUMBER> "The number of redirects allowed"))
-        .arg(arg!(--no-redirects "Disable any redirects").action(ArgAction::SetTrue))
+        .arg(arg!(--export <FORMAT> "Print the request as a command or snippet of code instead of sending it").value_parser(["curl", "httpie", "python", "js-fetch"]))
+        .arg(arg!(--"expect-status" <STATUS> "Fail unless the response has this status, either exact (201) or a class (2xx). May be repeated").action(ArgAction::Append))
+        .arg(arg!(--"expect-header" <HEADER> "Fail unless the response has this header (eg --expect-header \"Content-Type: application/json\")").action(ArgAction::Append))
+        .arg(arg!(--expect <EXPRESSION> "Fail unless the tera expression is true for the response (eg --expect 'id == 42')").action(ArgAction::Append))
+        .arg(arg!(--fail "Exit with an error when the response is not a 2xx").action(ArgAction::SetTrue))
+        .arg(arg!(--schema <FILE> "Validate the response body against the JSON Schema in the file"))
+        .arg(arg!(--validate "Check the request and response against the environment's OpenAPI document").action(ArgAction::SetTrue))
+        .arg(arg!(--snapshot <FILE> "Compare the response to the snapshot in the file, recording it if the file does not exist"))
+        .arg(arg!(--"update-snapshots" "Record the response into the --snapshot file, even if it already exists").action(ArgAction::SetTrue))
+        .arg(arg!(--"snapshot-header" <HEADER> "A header to include in the snapshot. May be repeated").action(ArgAction::Append))
+        .arg(arg!(--"snapshot-ignore" <POINTER> "A JSON pointer to a field in the body that changes between requests, such as /created_at or /items/*/id. May be repeated").action(ArgAction::Append))
+        .arg(arg!(--"http-version" <HTTP_VERSION> "The version of http to send the request as").value_parser(["0.9", "1.0", "1.1", "2.0", "3.0"]))
+        .arg(arg!(--"no-gzip" "Do not automatically uncompress gzip responses").action(ArgAction::SetTrue))
+        .arg(arg!(--"no-brotli" "Do not automatically uncompress brotli responses").action(ArgAction::SetTrue))
+        .arg(arg!(--"no-deflate" "Do not automatically uncompress deflate responses").action(ArgAction::SetTrue))
+        .arg(arg!(--"max-redirects" <NUMBER> "The number of redirects allowed").value_parser(clap::value_parser!(usize)))
+        .arg(arg!(--"no-redirects" "Disable any redirects").action(ArgAction::SetTrue))
         .arg(arg!(--proxy <PROXY> "The proxy to use for all requests."))
-        .arg(arg!(--proxy-http <PROXY_HTTP> "The proxy to use for http requests."))
-        .arg(arg!(--proxy-https <PROXY_HTTPS> "The proxy to use for https requests."))
-        .arg(arg!(--proxy-auth <PROXY_AUTH> "The username and password seperated by :."))
-        .arg(arg!(--connect-timeout <DURATION> "The amount of time to allow for connection"))
+        .arg(arg!(--"proxy-http" <PROXY_HTTP> "The proxy to use for http requests."))
+        .arg(arg!(--"proxy-https" <PROXY_HTTPS> "The proxy to use for https requests."))
+        .arg(arg!(--"proxy-auth" <PROXY_AUTH> "The username and password seperated by :."))
+        .arg(arg!(--"connect-timeout" <DURATION> "The amount of time to allow for connection"))
         .arg(arg!(--certificate <CERTIFICATE_FILE> "The path to the certificate to use for requests. Accepts PEM and DER, expects files to end in .der or .pem. defaults to pem").action(ArgAction::Append))
         .arg(Arg::new("args").action(ArgAction::Append))
-        .get_matches();
+}
 
-    match m.subcommand() {
-        Some(("environments", envs)) => run_environments(envs, &conf),
-        Some(("run", _)) => run_run(&conf).await,
-        _ => run_root(&m, &conf).await,
+// with_global_args adds the flags of kla itself to the command of a template or operation, so
+// they can also be given after its name, eg kla run get-user --id 1 --dry. The parameters of
+// the template win when they share a name. The environment is needed to find the template,
+// so it must still come first. The flags that were added are returned with the command.
+fn with_global_args(command: Command) -> (Command, Vec<Arg>) {
+    let own: Vec<Arg> = command.get_arguments().cloned().collect();
+    let taken = |arg: &Arg| {
+        own.iter().any(|own| {
+            own.get_id() == arg.get_id()
+                || (arg.get_long().is_some() && own.get_long() == arg.get_long())
+                || (arg.get_short().is_some() && own.get_short() == arg.get_short())
+        })
+    };
+
+    let global: Vec<Arg> = cli()
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && arg.get_id() != "env" && !taken(arg))
+        .map(|arg| arg.clone().hide(true))
+        .collect();
+    (command.args(global.clone()), global)
+}
+
+// merged_args merges the flags of kla that were given after the name of a template or
+// operation over those given before it, returning None when there were none. The later value
+// of a flag wins, while the values of a flag that can be repeated, eg --header, are combined.
+fn merged_args(args: &ArgMatches, matches: &ArgMatches, global: &[Arg]) -> Option<ArgMatches> {
+    let given =
+        |matches: &ArgMatches, id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let later: Vec<&str> = global
+        .iter()
+        .map(|arg| arg.get_id().as_str())
+        .filter(|id| given(matches, id))
+        .collect();
+    if later.is_empty() {
+        return None;
+    }
+
+    let mut argv = vec![OsString::from(crate_name!())];
+    for arg in cli().get_arguments() {
+        let id = arg.get_id().as_str();
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+
+        let repeated = matches!(arg.get_action(), ArgAction::Append | ArgAction::Count);
+        let sources: Vec<&ArgMatches> = match later.contains(&id) {
+            true if repeated && given(args, id) => vec![args, matches],
+            true => vec![matches],
+            false if given(args, id) => vec![args],
+            false => continue,
+        };
+
+        for source in sources {
+            match arg.get_action() {
+                ArgAction::Count => argv.extend(vec![
+                    OsString::from(format!("--{long}"));
+                    source.get_count(id).into()
+                ]),
+                ArgAction::SetTrue | ArgAction::SetFalse => argv.push(format!("--{long}").into()),
+                _ => {
+                    for value in source.get_raw(id).into_iter().flatten() {
+                        let mut flag = OsString::from(format!("--{long}="));
+                        flag.push(value);
+                        argv.push(flag);
+                    }
+                }
+            }
+        }
     }
+
+    Some(cli().get_matches_from(argv))
 }
 
 fn new_run() -> Command {
@@ -67,97 +284,1045 @@ fn new_run() -> Command {
             arg!(-e --env <ENVIRONMENT> "The environment we will run the request against")
                 .required(false),
         )
+        .arg(
+            arg!(--var <VAR> "Specify a variable key=value that can be used in the template")
+                .action(ArgAction::Append),
+        )
+}
+
+async fn run_run(args: &ArgMatches, run: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    let mut args = args;
+    let env_name =
+        run.get_one::<String>("env")
+            .or(args.get_one("env"))
+            .ok_or(Error::InvalidArguments(
+                "you must supply an environment to run a template".to_owned(),
+            ))?;
+    let env = kla::environment(Some(env_name), conf)?;
+
+    let (name, params) = match run.subcommand() {
+        Some(v) => v,
+        None => return run_templates(&env),
+    };
+
+    let template = env.template(name)?;
+    let (command, global) = with_global_args(template.command(name));
+    let matches = command.get_matches_from(
+        params
+            .get_many::<OsString>("")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    let merged = merged_args(args, &matches, &global);
+    if let Some(merged) = &merged {
+        args = merged;
+    }
+    logger(args.get_count("verbose"));
+
+    let context = template.context(&matches, kla::variables(&env, Some(vars(args, run)))?);
+    let mut template = template
+        .render_resolved(&context, &env.key(&format!("template.{name}")))?
+        .inherit(&env, &context)?;
+    if let Some(schema) = args.get_one::<String>("schema") {
+        template.schema = Some(schema.clone());
+    }
+
+    let response = send_template(
+        args,
+        TemplateBuilder::new_opt_file(args.get_one("output"))?,
+        client(args, &env)?,
+        &env,
+        &template,
+        context,
+    )
+    .await?;
+
+    // the schema was checked when the template was sent
+    match response {
+        Some(response) => {
+            expect(args, &response)?;
+            snapshot(args, &response)
+        }
+        None => Ok(()),
+    }
+}
+
+async fn run_op(args: &ArgMatches, op: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    let mut args = args;
+    let env_name =
+        op.get_one::<String>("env")
+            .or(args.get_one("env"))
+            .ok_or(Error::InvalidArguments(
+                "you must supply an environment to call an operation".to_owned(),
+            ))?;
+    let env = kla::environment(Some(env_name), conf)?;
+    let api = env.openapi()?;
+
+    let (id, params) = match op.subcommand() {
+        Some(v) => v,
+        None => return run_operations(api),
+    };
+
+    let operation = api.operation(id)?;
+    let (command, global) = with_global_args(operation.command(api));
+    let matches = command.get_matches_from(
+        params
+            .get_many::<OsString>("")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+    let merged = merged_args(args, &matches, &global);
+    if let Some(merged) = &merged {
+        args = merged;
+    }
+    logger(args.get_count("verbose"));
+    let context = kla::variables(&env, Some(vars(args, op)))?;
+    let template = operation
+        .template(api, &matches)?
+        .render(&context)?
+        .inherit(&env, &context)?;
+
+    let response = send_template(
+        args,
+        TemplateBuilder::new_opt_file(args.get_one("output"))?,
+        client(args, &env)?,
+        &env,
+        &template,
+        context,
+    )
+    .await?;
+
+    match response {
+        Some(response) => check_response(args, &response),
+        None => Ok(()),
+    }
+}
+
+fn run_operations(api: &OpenApi) -> Result<(), Error> {
+    for operation in api.operations() {
+        println!(
+            "{} = {} {} {}",
+            operation.id,
+            operation.method,
+            operation.path,
+            operation.summary.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+fn run_templates(env: &Environment) -> Result<(), Error> {
+    for (name, description) in env.templates() {
+        println!(
+            "{name} = {}",
+            description.map(|v| v.as_str()).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+async fn run_import_curl(
+    args: &ArgMatches,
+    import: &ArgMatches,
+    conf: &Config,
+) -> Result<(), Error> {
+    let command: Vec<String> = import
+        .get_many::<String>("command")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let curl = match &command[..] {
+        [command] => Curl::parse(command)?,
+        command => Curl::from_args(command)?,
+    };
+    let env_name = import.get_one::<String>("env").or(args.get_one("env"));
+
+    if let Some(name) = import.get_one::<String>("name") {
+        let env_name = env_name.ok_or(Error::InvalidArguments(
+            "you must supply an environment to save the template into".to_owned(),
+        ))?;
+        let path = import.get_one::<String>("config").unwrap();
+        let env = Environment::new(env_name, conf).ok();
+
+        if curl.insecure || curl.cacert.is_some() {
+            eprintln!("warning: -k and --cacert are not saved with templates, set certificate on the environment instead");
+        }
+
+        let mut config = ConfigFile::open(path)?;
+        config.set_template(
+            env_name,
+            name,
+            &curl.relative_to(env.as_ref().and_then(|env| env.url.as_ref()))?,
+        )?;
+        config.save()?;
+
+        eprintln!("saved template {name} to environment {env_name} in {path}");
+        return Ok(());
+    }
+
+    let env = kla::environment(env_name, conf)?;
+    let context = kla::variables(&env, args.get_many("var"))?;
+    let template = curl.template.clone().inherit(&env, &context)?;
+
+    let response = send_template(
+        args,
+        TemplateBuilder::new_opt_file(args.get_one("output"))?,
+        client_builder(args, &env)?
+            .danger_accept_invalid_certs(curl.insecure)
+            .opt_certificate(Some(curl.cacert.iter()))?
+            .build()?,
+        &env,
+        &template,
+        context,
+    )
+    .await?;
+
+    match response {
+        Some(response) => check_response(args, &response),
+        None => Ok(()),
+    }
+}
+
+// run_import_openapi saves a template named by the operationId of each operation into the
+// environment. When the document lists more than one server, an environment extending it
+// is created for each of them.
+fn run_import_openapi(args: &ArgMatches, import: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    let env_name = import
+        .get_one::<String>("env")
+        .or(args.get_one("env"))
+        .ok_or(Error::InvalidArguments(
+            "you must supply an environment to save the templates into".to_owned(),
+        ))?;
+    let file = import.get_one::<String>("file").unwrap();
+    let path = import.get_one::<String>("config").unwrap();
+    let api = OpenApi::open(file)?;
+    let env = Environment::new(env_name, conf).ok();
+
+    // relative servers, such as /v1, can not be used as the url of an environment
+    let servers: Vec<(String, Option<String>)> = api
+        .servers()
+        .into_iter()
+        .filter(|(url, _)| url.contains("://"))
+        .collect();
+
+    let mut config = ConfigFile::open(path)?;
+    if env.as_ref().and_then(|env| env.url.as_ref()).is_none() {
+        if let Some((url, _)) = servers.first() {
+            config.set_environment_value(env_name, "url", url)?;
+        }
+    }
+    if env.as_ref().and_then(|env| env.openapi.as_ref()).is_none() {
+        config.set_environment_value(env_name, "openapi", file)?;
+    }
+
+    if servers.len() > 1 {
+        for (i, (url, description)) in servers.iter().enumerate() {
+            let name = description
+                .as_deref()
+                .map(environment_name)
+                .filter(|name| !name.is_empty() && name != env_name)
+                .unwrap_or_else(|| format!("{env_name}-{}", i + 1));
+            config.set_environment_value(&name, "extends", env_name)?;
+            config.set_environment_value(&name, "url", url)?;
+            eprintln!("saved environment {name} for {url}");
+        }
+    }
+
+    let operations = api.operations();
+    for operation in operations.iter() {
+        config.set_template(env_name, &operation.id, &operation.request_template(&api))?;
+    }
+    config.save()?;
+
+    eprintln!(
+        "saved {} templates to environment {env_name} in {path}",
+        operations.len()
+    );
+    Ok(())
+}
+
+// environment_name turns the description of a server into the name of an environment, so
+// "Staging server" becomes staging-server
+fn environment_name(description: &str) -> String {
+    description
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+async fn run_file(args: &ArgMatches, file: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    let http = HttpFile::open(file.get_one::<String>("file").unwrap())?;
+    let env = kla::environment(file.get_one("env").or(args.get_one("env")), conf)?;
+    let context = http.context(kla::variables(&env, Some(vars(args, file)))?)?;
+
+    let requests = match file.get_one::<String>("name") {
+        Some(name) => vec![http.request(name)?],
+        None => http.requests.iter().collect(),
+    };
+
+    // the output file is shared by all of the requests, rather than each one truncating it
+    let output = args
+        .get_one::<String>("output")
+        .map(File::create)
+        .transpose()?;
+    let writer = || -> Result<Box<dyn Write>, Error> {
+        Ok(match &output {
+            Some(file) => Box::new(file.try_clone()?),
+            None => Box::new(io::stdout()),
+        })
+    };
+
+    for (i, http_request) in requests.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer()?)?;
+        }
+
+        let template = http_request
+            .template
+            .render(&context)?
+            .inherit(&env, &context)?;
+
+        let response = send_template(
+            args,
+            TemplateBuilder::new(writer()?),
+            client(args, &env)?,
+            &env,
+            &template,
+            context.clone(),
+        )
+        .await?;
+
+        if let Some(response) = response.filter(|_| i + 1 == requests.len()) {
+            check_response(args, &response)?;
+        }
+    }
+
+    Ok(())
 }
 
-async fn run_run(_conf: &Config) -> Result<(), Error> {
-    // let env = kla::environment(args.get_one("env"), conf);
+async fn run_hurl(args: &ArgMatches, hurl: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    let file = HurlFile::open(hurl.get_one::<String>("file").unwrap())?;
+    let env = kla::environment(hurl.get_one("env").or(args.get_one("env")), conf)?;
+    let context = kla::variables(&env, Some(vars(args, hurl)))?;
 
-    let m = command!()
-        .subcommand(new_run().subcommand(Command::new("test").about(
-            "this is a test, it will run nothing and do nothing, because it is nothing, just like the rest of us... and you. NOTHING.",
-        )))
-        .get_matches();
+    run_hurl_file(args, &env, &file, context, false).await
+}
+
+// run_hurl_file runs each entry of the file in turn, checking the asserts and capturing
+// values for the entries that follow. Like hurl itself, only the last response is written
+// out, and none are when quiet is set.
+async fn run_hurl_file(
+    args: &ArgMatches,
+    env: &Environment,
+    file: &HurlFile,
+    mut context: Context,
+    quiet: bool,
+) -> Result<(), Error> {
+    for (i, entry) in file.entries.iter().enumerate() {
+        let template = entry.template.render(&context)?.inherit(env, &context)?;
+
+        let output = match quiet {
+            true => TemplateBuilder::new(Box::new(io::sink())),
+            false => step_output(args, i + 1 == file.entries.len())?,
+        };
+
+        let response = send_template(
+            args,
+            output,
+            client(args, env)?,
+            env,
+            &template,
+            context.clone(),
+        )
+        .await?;
 
-    match m.subcommand() {
-        Some(("test", _)) => println!("test"),
-        _ => println!("no test"),
+        let Some(response) = response else {
+            // nothing was sent, so the requests that follow are shown with a placeholder
+            // in place of each value it would have captured
+            for capture in entry.captures.iter() {
+                context.insert(&capture.name, &captured_placeholder(&capture.name));
+            }
+            continue;
+        };
+
+        entry.check(&response)?;
+        entry.capture(&response, &mut context)?;
+        if !quiet && i + 1 == file.entries.len() {
+            check_response(args, &response)?;
+        }
     }
 
     Ok(())
 }
 
+async fn run_test(args: &ArgMatches, test: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    // each file would be compared to the same schema or snapshot, asserts are the way to
+    // check the responses of a test
+    if args.contains_id("schema") || args.contains_id("snapshot") {
+        return Err(Error::InvalidArguments(
+            "--schema and --snapshot can not be used with test, use asserts in the hurl files instead".to_owned(),
+        ));
+    }
+
+    let env = kla::environment(test.get_one("env").or(args.get_one("env")), conf)?;
+    let context = kla::variables(&env, Some(vars(args, test)))?;
+    let paths = HurlFile::discover(test.get_one::<String>("path").unwrap())?;
+    let jobs = *test.get_one::<usize>("jobs").unwrap();
+
+    // buffered keeps the results in the same order as the files, however they finish
+    let report = TestReport::new(
+        stream::iter(paths.iter())
+            .map(|path| run_test_file(args, &env, path, context.clone()))
+            .buffered(jobs.max(1))
+            .collect()
+            .await,
+    );
+
+    report.write_summary(&mut io::stdout())?;
+    if let Some(path) = test.get_one::<String>("junit") {
+        report.write_junit(&mut File::create(path)?)?;
+    }
+    if let Some(path) = test.get_one::<String>("tap") {
+        report.write_tap(&mut File::create(path)?)?;
+    }
+
+    if report.failed() > 0 {
+        return Err(Error::AssertionFailed(format!(
+            "{} of {} tests failed",
+            report.failed(),
+            report.results.len()
+        )));
+    }
+
+    Ok(())
+}
+
+async fn run_test_file(
+    args: &ArgMatches,
+    env: &Environment,
+    path: &str,
+    context: Context,
+) -> TestResult {
+    let start = Instant::now();
+    let result = match HurlFile::open(path) {
+        Ok(file) => run_hurl_file(args, env, &file, context, true).await,
+        Err(err) => Err(err),
+    };
+
+    TestResult {
+        name: path.to_owned(),
+        duration: start.elapsed(),
+        outcome: match result {
+            Ok(()) => Outcome::Passed,
+            Err(
+                Error::AssertionFailed(msg) | Error::StatusFailed(msg) | Error::SpecViolation(msg),
+            ) => Outcome::Failed(msg),
+            Err(err) => Outcome::Errored(message(&err)),
+        },
+    }
+}
+
+async fn run_workflow(
+    args: &ArgMatches,
+    workflow: &ArgMatches,
+    conf: &Config,
+) -> Result<(), Error> {
+    let env_name = workflow
+        .get_one::<String>("env")
+        .or(args.get_one("env"))
+        .ok_or(Error::InvalidArguments(
+            "you must supply an environment to run a workflow".to_owned(),
+        ))?;
+    let env = kla::environment(Some(env_name), conf)?;
+
+    let Some(name) = workflow.get_one::<String>("name") else {
+        return run_workflows(&env);
+    };
+
+    let steps = env.workflow(name)?.steps;
+    let mut context = kla::variables(&env, Some(vars(args, workflow)))?;
+
+    for (i, step) in steps.iter().enumerate() {
+        let template = env.template(&step.template)?;
+        let step_context = step.context(&template, context.clone())?;
+        let template = template
+            .render_resolved(
+                &step_context,
+                &env.key(&format!("template.{}", step.template)),
+            )?
+            .inherit(&env, &step_context)?;
+
+        let response = send_template(
+            args,
+            step_output(args, i + 1 == steps.len())?,
+            client(args, &env)?,
+            &env,
+            &template,
+            step_context,
+        )
+        .await?;
+
+        let Some(response) = response else {
+            // nothing was sent, so the steps that follow are shown with a placeholder in
+            // place of each value it would have captured
+            for name in step.capture.keys() {
+                context.insert(name, &captured_placeholder(name));
+            }
+            continue;
+        };
+
+        // there is no point carrying on once a step has failed, eg when the login is rejected
+        let status = response
+            .get("resp_status")
+            .and_then(|status| status.as_str())
+            .unwrap_or_default();
+        if i + 1 < steps.len() && !status.starts_with('2') {
+            return Err(Error::ClientError(format!(
+                "step {} of workflow {name} ({}) responded with {status}",
+                i + 1,
+                step.template
+            )));
+        }
+
+        step.capture(&response, &mut context)?;
+        if i + 1 == steps.len() {
+            check_response(args, &response)?;
+        }
+    }
+
+    Ok(())
+}
+
+// captured_placeholder is shown in place of a captured value when the request it comes from
+// is not sent, eg for --dry
+fn captured_placeholder(name: &str) -> String {
+    format!("<captured {name}>")
+}
+
+fn run_workflows(env: &Environment) -> Result<(), Error> {
+    for (name, description) in env.workflows() {
+        println!(
+            "{name} = {}",
+            description.map(|v| v.as_str()).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+async fn run_login(args: &ArgMatches, login: &ArgMatches, conf: &Config) -> Result<(), Error> {
+    let env_name = login
+        .get_one::<String>("env")
+        .or(args.get_one("env"))
+        .ok_or(Error::InvalidArguments(
+            "you must supply an environment to log in to".to_owned(),
+        ))?;
+    let env = kla::environment(Some(env_name), conf)?;
+    let oauth2 = env.oauth2.as_ref().ok_or(Error::ConfigError(format!(
+        "environment {env_name} does not have oauth2 settings"
+    )))?;
+
+    oauth2
+        .login(
+            &client(args, &env)?,
+            env_name,
+            !login.get_flag("no-browser"),
+        )
+        .await?;
+    eprintln!("logged in to {env_name}");
+    Ok(())
+}
+
 fn run_environments(args: &ArgMatches, conf: &Config) -> Result<(), Error> {
     let r = Regex::new(args.get_one::<String>("regex").unwrap())?;
 
-    conf.get_table("environment")?
-        .iter()
-        .map(|(k, _)| k)
+    // an environment that can not be loaded, eg because it extends one that does not exist,
+    // is reported without hiding the rest
+    let mut failed = 0;
+    for k in conf
+        .get_table("environment")?
+        .keys()
         .filter(|k| r.is_match(k))
-        .for_each(|k| {
-            println!(
-                "{k} = {}",
-                conf.get_string(format!("environment.{k}.url").as_str())
-                    .unwrap_or_default()
-            )
-        });
-    Ok(())
+    {
+        match Environment::new(k, conf) {
+            Ok(env) => match env.extends {
+                Some(parent) => {
+                    println!("{k} = {} (extends {parent})", env.url.unwrap_or_default())
+                }
+                None => println!("{k} = {}", env.url.unwrap_or_default()),
+            },
+            Err(err) => {
+                eprintln!("{k}: {}", message(&err));
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::ConfigError(format!(
+            "{failed} environments could not be loaded"
+        ))),
+    }
 }
 
 async fn run_root(args: &ArgMatches, conf: &Config) -> Result<(), Error> {
-    let env = kla::environment(args.get_one("env"), conf);
+    let env = kla::environment(args.get_one("env"), conf)?;
+    let context = kla::variables(&env, args.get_many("var"))?;
+    let template = RequestTemplate::default().inherit(&env, &context)?;
+    let uri_args = render_args(args, "args", &context)?;
+    let client = client(args, &env)?;
+    let token = access_token(args, &env, &template, &client).await?;
 
-    TemplateBuilder::new_opt_file(args.get_one("output"))?
+    let output = TemplateBuilder::new_opt_file(args.get_one("output"))?
+        .verbose(args.get_count("verbose"))
         .opt_template(args.get_one("template"))?
         .opt_failure_template(args.get_one("failure-template"))?
-        .request(
-            ClientBuilder::new()
-                .opt_header_agent(args.get_one("agent"))?
-                .gzip(
-                    !args
-                        .get_one::<bool>("no-gzip")
-                        .map(|v| *v)
-                        .unwrap_or_default(),
-                )
-                .brotli(
-                    !args
-                        .get_one::<bool>("no-brotli")
-                        .map(|v| *v)
-                        .unwrap_or_default(),
-                )
-                .deflate(
-                    !args
-                        .get_one::<bool>("no-deflate")
-                        .map(|v| *v)
-                        .unwrap_or_default(),
-                )
-                .connection_verbose(
-                    args.get_one::<bool>("verbose")
-                        .map(|v| *v)
-                        .unwrap_or_default(),
-                )
-                .opt_max_redirects(args.get_one("max-redirects"))
-                .no_redirects(
-                    args.get_one::<bool>("no_redirects")
-                        .map(|v| *v)
-                        .unwrap_or_default(),
-                )
-                .opt_proxy(args.get_one("proxy"), args.get_one("proxy-auth"))?
-                .opt_proxy_http(args.get_one("proxy-http"), args.get_one("proxy-auth"))?
-                .opt_proxy_https(args.get_one("proxy-https"), args.get_one("proxy-auth"))?
-                .opt_certificate(args.get_many("certificate"))?
-                .build()?
-                .args(args.get_many("args"), env.as_ref())?
-                .opt_headers(args.get_many("header"))?
-                .opt_bearer_auth(args.get_one("bearer-token"))
-                .opt_basic_auth(args.get_one("basic-auth"))
-                .opt_query(args.get_many("query"))?
-                .opt_form(args.get_many("form"))?
-                .opt_timeout(args.get_one("timeout"))?
-                .opt_version(args.get_one("http-version"))?,
-        )
-        .build()?
-        .send()
-        .await?;
+        .request(request(
+            client.args(
+                uri_args.as_ref().map(|v| v.iter()),
+                env.url(&context)?.as_ref(),
+            )?,
+            args,
+            &env,
+            &template,
+            &context,
+            token.as_ref(),
+        )?)
+        .opt_oauth2(env.oauth2.as_ref().filter(|_| token.is_some()), &env.name)
+        .opt_digest_auth(args.get_one("digest-auth"))
+        .opt_signing(env.signing.as_ref(), &env.key("signing"))
+        .context(context)
+        .build()?;
+
+    match send(output, args, &env).await? {
+        Some(response) => check_response(args, &response),
+        None => Ok(()),
+    }
+}
+
+// check_response checks the response against the expectations, --schema and --snapshot.
+// They describe a single response, so when a command sends several requests only the last
+// is checked.
+fn check_response(args: &ArgMatches, response: &Context) -> Result<(), Error> {
+    expect(args, response)?;
+    validate(args.get_one("schema"), response)?;
+    snapshot(args, response)
+}
+
+// expect checks the response against --expect-status, --expect-header, --expect and --fail
+fn expect(args: &ArgMatches, response: &Context) -> Result<(), Error> {
+    Expectations::new()
+        .opt_status(args.get_many("expect-status"))?
+        .opt_headers(args.get_many("expect-header"))?
+        .opt_expressions(args.get_many("expect"))
+        .fail(args.get_flag("fail"))
+        .check(response)
+}
+
+// validate checks the response body against the JSON schema in the file, if there is one
+fn validate(schema: Option<&String>, response: &Context) -> Result<(), Error> {
+    match schema {
+        Some(path) => Schema::open(path)?.validate(response),
+        None => Ok(()),
+    }
+}
+
+// snapshot compares the response to the snapshot given with --snapshot. The response is
+// recorded instead when there is no snapshot yet, or when --update-snapshots is set.
+fn snapshot(args: &ArgMatches, response: &Context) -> Result<(), Error> {
+    let Some(path) = args.get_one::<String>("snapshot") else {
+        return Ok(());
+    };
+    let expected = Snapshot::load(path)?;
+
+    // the headers and ignored fields given on the command line replace the recorded ones
+    let headers: Vec<String> = match args.get_many::<String>("snapshot-header") {
+        Some(headers) => headers.cloned().collect(),
+        None => expected
+            .as_ref()
+            .map(|s| s.headers.keys().cloned().collect())
+            .unwrap_or_default(),
+    };
+    let ignore: Vec<String> = match args.get_many::<String>("snapshot-ignore") {
+        Some(ignore) => ignore.cloned().collect(),
+        None => expected
+            .as_ref()
+            .map(|s| s.ignore.clone())
+            .unwrap_or_default(),
+    };
+    let actual = Snapshot::from_response(response, &headers, &ignore);
+
+    match expected {
+        Some(expected) if !args.get_flag("update-snapshots") => {
+            let changes = expected.diff(&actual);
+            if !changes.is_empty() {
+                return Err(Error::AssertionFailed(format!(
+                    "the response does not match the snapshot {path}\n{}",
+                    changes.join("\n")
+                )));
+            }
+        }
+        _ => {
+            actual.save(path)?;
+            eprintln!("snapshot written to {path}");
+        }
+    }
 
     Ok(())
 }
+
+// step_output is where the response of a step in a scenario is written. Only the last
+// response is written to the output, unless this is a dry run or an export, where every
+// request is.
+fn step_output(args: &ArgMatches, last: bool) -> Result<TemplateBuilder, Error> {
+    if last || args.get_flag("dry") || args.get_one::<String>("export").is_some() {
+        return TemplateBuilder::new_opt_file(args.get_one("output"));
+    }
+    Ok(TemplateBuilder::new(Box::new(io::sink())))
+}
+
+// message returns the detail held by the error, falling back to the description of the
+// error when it does not have any.
+fn message(err: &Error) -> String {
+    match err {
+        Error::BodyParsingError(msg)
+        | Error::ConfigError(msg)
+        | Error::ClientError(msg)
+        | Error::TemplateError(msg)
+        | Error::InvalidArguments(msg)
+        | Error::IOError(msg)
+        | Error::AssertionFailed(msg)
+        | Error::StatusFailed(msg)
+        | Error::SpecViolation(msg) => msg.clone(),
+        err => err.to_string(),
+    }
+}
+
+// vars returns the variables passed to the root command followed by the ones passed to the
+// subcommand, so the subcommand's take precedence.
+fn vars<'a>(args: &'a ArgMatches, sub: &'a ArgMatches) -> impl Iterator<Item = &'a String> {
+    args.get_many::<String>("var")
+        .into_iter()
+        .flatten()
+        .chain(sub.get_many::<String>("var").into_iter().flatten())
+}
+
+// logger writes the logs from the http client to stderr when running with -vv or higher
+fn logger(verbose: u8) {
+    let level = match verbose {
+        0 | 1 => return,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    env_logger::Builder::new()
+        .filter_module("reqwest", level)
+        .filter_module("hyper", level)
+        .filter_module("native_tls", level)
+        .init();
+}
+
+// send_template sends the template through the same pipeline as the root command. The
+// output and failure templates on the command line take precedence over the template's own.
+// The response is validated against the template's schema when it has one.
+async fn send_template(
+    args: &ArgMatches,
+    output: TemplateBuilder,
+    client: Client,
+    env: &Environment,
+    template: &RequestTemplate,
+    context: Context,
+) -> Result<Option<Context>, Error> {
+    let template_args = template.args();
+    let token = access_token(args, env, template, &client).await?;
+
+    let output = output
+        .verbose(args.get_count("verbose"))
+        .opt_template(args.get_one("template").or(template.template.as_ref()))?
+        .opt_failure_template(
+            args.get_one("failure-template")
+                .or(template.failure_template.as_ref()),
+        )?
+        .request(request(
+            client.args(Some(template_args.iter()), env.url(&context)?.as_ref())?,
+            args,
+            env,
+            template,
+            &context,
+            token.as_ref(),
+        )?)
+        .opt_oauth2(env.oauth2.as_ref().filter(|_| token.is_some()), &env.name)
+        .opt_digest_auth(args.get_one("digest-auth"))
+        .opt_signing(env.signing.as_ref(), &env.key("signing"))
+        .context(context)
+        .build()?;
+
+    let response = send(output, args, env).await?;
+    if let Some(response) = &response {
+        validate(template.schema.as_ref(), response)?;
+    }
+    Ok(response)
+}
+
+// send sends the request, unless this is a dry run or an export, in which case the request
+// is printed instead. The response context is returned when the request was sent. With
+// --validate the request is checked against the environment's OpenAPI document before it
+// is sent, and the response after it is received.
+async fn send(
+    template: Template,
+    args: &ArgMatches,
+    env: &Environment,
+) -> Result<Option<Context>, Error> {
+    let contract = match args.get_flag("validate") {
+        true => {
+            let api = env.openapi()?;
+            let request = template.request()?;
+            api.validate_request(&request)?;
+            Some((api, request))
+        }
+        false => None,
+    };
+
+    if args.get_flag("dry") {
+        template.dry()?;
+        return Ok(None);
+    }
+
+    if let Some(format) = args.get_one::<String>("export") {
+        template.export(format)?;
+        return Ok(None);
+    }
+
+    let response = template.send().await?;
+    if let Some((api, request)) = contract {
+        api.validate_response(&request, &response)?;
+    }
+
+    Ok(Some(response))
+}
+
+// client builds the http client from the arguments passed on the command line, falling back
+// to the settings in the environment.
+fn client(args: &ArgMatches, env: &Environment) -> Result<Client, Error> {
+    Ok(client_builder(args, env)?.build()?)
+}
+
+fn client_builder(args: &ArgMatches, env: &Environment) -> Result<ClientBuilder, Error> {
+    let certificates = env
+        .certificate
+        .iter()
+        .chain(args.get_many::<String>("certificate").into_iter().flatten());
+
+    ClientBuilder::new()
+        .opt_header_agent(agent(args, env))?
+        .gzip(
+            !args
+                .get_one::<bool>("no-gzip")
+                .map(|v| *v)
+                .unwrap_or_default(),
+        )
+        .brotli(
+            !args
+                .get_one::<bool>("no-brotli")
+                .map(|v| *v)
+                .unwrap_or_default(),
+        )
+        .deflate(
+            !args
+                .get_one::<bool>("no-deflate")
+                .map(|v| *v)
+                .unwrap_or_default(),
+        )
+        .tls_info(args.get_count("verbose") > 1)
+        .connection_verbose(args.get_count("verbose") > 2)
+        .opt_max_redirects(args.get_one("max-redirects"))
+        .no_redirects(
+            args.get_one::<bool>("no-redirects")
+                .map(|v| *v)
+                .unwrap_or_default(),
+        )
+        .opt_proxy(
+            match args.get_one::<String>("proxy") {
+                Some(proxy) => Some(proxy.clone()),
+                None => env.proxy()?,
+            }
+            .as_ref(),
+            args.get_one("proxy-auth"),
+        )?
+        .opt_proxy_http(args.get_one("proxy-http"), args.get_one("proxy-auth"))?
+        .opt_proxy_https(args.get_one("proxy-https"), args.get_one("proxy-auth"))?
+        .opt_connect_timeout(
+            args.get_one("connect-timeout")
+                .or(env.connect_timeout.as_ref()),
+        )?
+        .opt_certificate(Some(certificates))
+}
+
+// access_token fetches an access token with the environment's oauth2 credentials. It is
+// only needed when no other auth was given, either on the command line or by the template.
+// Dry runs and exports only show the request, so they get a placeholder rather than calling
+// the token endpoint and printing a live token.
+async fn access_token(
+    args: &ArgMatches,
+    env: &Environment,
+    template: &RequestTemplate,
+    client: &Client,
+) -> Result<Option<String>, Error> {
+    let Some(oauth2) = &env.oauth2 else {
+        return Ok(None);
+    };
+    if args.get_one::<String>("bearer-token").is_some()
+        || args.get_one::<String>("basic-auth").is_some()
+        || args.get_one::<String>("digest-auth").is_some()
+        || template.basic_auth.is_some()
+    {
+        return Ok(None);
+    }
+    if args.get_flag("dry") || args.get_one::<String>("export").is_some() {
+        return Ok(Some(ACCESS_TOKEN_PLACEHOLDER.to_owned()));
+    }
+
+    Ok(Some(oauth2.token(client, &env.name).await?))
+}
+
+// agent returns the User-Agent to send, the environment's own replaces the default
+fn agent<'a>(args: &'a ArgMatches, env: &'a Environment) -> Option<&'a String> {
+    match args.value_source("agent") {
+        Some(ValueSource::DefaultValue) => env.agent.as_ref().or(args.get_one("agent")),
+        _ => args.get_one("agent"),
+    }
+}
+
+// default_headers returns the headers the client adds to every request it sends, the same as
+// reqwest adds them, so they can be set on the request before it is printed or signed.
+fn default_headers(args: &ArgMatches, env: &Environment) -> Result<HeaderMap, Error> {
+    // the default agent is only a placeholder, which an exported request is better without
+    let placeholder = args.get_one::<String>("export").is_some()
+        && env.agent.is_none()
+        && args.value_source("agent") == Some(ValueSource::DefaultValue);
+
+    let mut headers = HeaderMap::new();
+    if let Some(agent) = agent(args, env).filter(|_| !placeholder) {
+        headers.insert(USER_AGENT, HeaderValue::from_str(agent)?);
+    }
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+
+    let encodings: Vec<&str> = [
+        ("no-gzip", "gzip"),
+        ("no-brotli", "br"),
+        ("no-deflate", "deflate"),
+    ]
+    .into_iter()
+    .filter(|(flag, _)| !args.get_flag(flag))
+    .map(|(_, encoding)| encoding)
+    .collect();
+    if !encodings.is_empty() {
+        headers.insert(
+            ACCEPT_ENCODING,
+            HeaderValue::from_str(&encodings.join(", "))?,
+        );
+    }
+    Ok(headers)
+}
+
+// request applies the request level arguments passed on the command line on top of the
+// defaults from the template and environment, so the command line always takes precedence.
+// The headers, query and form values from the command line are rendered with the context.
+// The access token from the environment's oauth2 credentials is used in place of the
+// environment's own auth.
+fn request(
+    builder: RequestBuilder,
+    args: &ArgMatches,
+    env: &Environment,
+    template: &RequestTemplate,
+    context: &Context,
+    token: Option<&String>,
+) -> Result<RequestBuilder, Error> {
+    let headers = template.headers();
+    let query = template.query().unwrap_or_default();
+    let form = template.form().unwrap_or_default();
+    let arg_headers = render_args(args, "header", context)?;
+    let arg_query = render_args(args, "query", context)?;
+    let arg_form = render_args(args, "form", context)?;
+
+    // only fall back to the environment when no auth was given, otherwise both would end
+    // up in the Authorization header. Digest auth is answered once the server challenges it.
+    // The environment's auth is only resolved here, so its secrets are left alone when the
+    // command line overrides them.
+    let (bearer_token, basic_auth) = match (
+        args.get_one::<String>("bearer-token"),
+        args.get_one::<String>("basic-auth"),
+    ) {
+        _ if args.get_one::<String>("digest-auth").is_some() => (None, None),
+        (None, None) => match (template.basic_auth.as_ref(), token) {
+            (Some(basic_auth), _) => (None, Some(basic_auth.clone())),
+            (None, Some(token)) => (Some(token.clone()), None),
+            (None, None) => (env.bearer_token()?, env.basic_auth()?),
+        },
+        (bearer_token, basic_auth) => (bearer_token.cloned(), basic_auth.cloned()),
+    };
+
+    // sigv4 puts its signature in the Authorization header, so there is no room for other
+    // credentials, and it could not be redone when they are swapped after a 401
+    let sigv4 = args.get_one("aws-sigv4").or(env.aws_sigv4.as_ref());
+    if sigv4.is_some() && (token.is_some() || args.get_one::<String>("digest-auth").is_some()) {
+        return Err(Error::InvalidArguments(
+            "--aws-sigv4 can not be combined with --digest-auth or an oauth2 access token"
+                .to_owned(),
+        ));
+    }
+
+    builder
+        .opt_data(template.body.as_ref())
+        .opt_body_file(template.body_file.as_ref())?
+        .opt_headers(headers.as_ref().map(|v| v.iter()))?
+        .opt_headers(arg_headers.as_ref().map(|v| v.iter()))?
+        .opt_bearer_auth(bearer_token.as_ref())
+        .opt_basic_auth(basic_auth.as_ref())
+        .opt_query(merge(&query, arg_query.as_deref()))?
+        .opt_form(merge(&form, arg_form.as_deref()))?
+        .opt_multipart(template.multipart().as_ref().map(|v| v.iter()))?
+        .opt_timeout(args.get_one("timeout").or(env.timeout.as_ref()))?
+        .opt_version(args.get_one("http-version"))?
+        .default_headers(&default_headers(args, env)?)?
+        .opt_signing(env.signing.as_ref(), context, &env.key("signing"))?
+        .opt_aws_sigv4(sigv4)
+}
+
+// render_args renders the values of the argument with the context, unless --raw asks for
+// them to be sent as they are, eg a header holding a literal {{
+fn render_args(
+    args: &ArgMatches,
+    id: &str,
+    context: &Context,
+) -> Result<Option<Vec<String>>, Error> {
+    match args.get_flag("raw") {
+        true => Ok(args
+            .get_many::<String>(id)
+            .map(|values| values.cloned().collect())),
+        false => kla::render_all(args.get_many(id), context).map_err(|err| match err {
+            Error::TemplateError(msg) => Error::TemplateError(format!(
+                "{msg}, use --raw to send the command line values as they are"
+            )),
+            err => err,
+        }),
+    }
+}
+
+// merge places the values from the command line after the defaults, so they win when the
+// same key is given twice.
+fn merge<'a>(
+    defaults: &'a [String],
+    args: Option<&'a [String]>,
+) -> Option<impl Iterator<Item = &'a String>> {
+    if defaults.is_empty() && args.is_none() {
+        return None;
+    }
+
+    Some(defaults.iter().chain(args.into_iter().flatten()))
+}
@@ -0,0 +1,78 @@
+use crate::{Error, RequestTemplate};
+use std::{fs, path::Path};
+use toml_edit::{Document, Item, Table};
+
+// ConfigFile is a config file opened for editing. It is used when kla writes to the config
+// itself, and keeps any comments and formatting that are already in the file.
+pub struct ConfigFile {
+    path: String,
+    document: Document,
+}
+
+impl ConfigFile {
+    // open reads the config file at path, starting from an empty config when the file does
+    // not exist yet.
+    pub fn open(path: &str) -> Result<ConfigFile, Error> {
+        let document = if Path::new(path).exists() {
+            fs::read_to_string(path)?.parse::<Document>()?
+        } else {
+            Document::new()
+        };
+
+        Ok(ConfigFile {
+            path: path.to_owned(),
+            document,
+        })
+    }
+
+    // set_template saves the template under `[environment.<env>.template.<name>]`,
+    // replacing any template that already has the name.
+    pub fn set_template(
+        &mut self,
+        env: &str,
+        name: &str,
+        template: &RequestTemplate,
+    ) -> Result<(), Error> {
+        let mut table = toml_edit::ser::to_document(template)?.as_table().clone();
+        table.set_implicit(false);
+
+        self.table(&["environment", env, "template"])?
+            .insert(name, Item::Table(table));
+        Ok(())
+    }
+
+    // set_environment_value sets a single value, such as the url, on `[environment.<env>]`
+    pub fn set_environment_value(
+        &mut self,
+        env: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let table = self.table(&["environment", env])?;
+        table.set_implicit(false);
+        table.insert(key, toml_edit::value(value));
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        fs::write(&self.path, self.document.to_string())?;
+        Ok(())
+    }
+
+    // table walks down the path of keys, creating any tables that are missing along the way
+    fn table(&mut self, path: &[&str]) -> Result<&mut Table, Error> {
+        let mut table = self.document.as_table_mut();
+        for key in path {
+            table = table
+                .entry(key)
+                .or_insert_with(|| {
+                    let mut table = Table::new();
+                    table.set_implicit(true);
+                    Item::Table(table)
+                })
+                .as_table_mut()
+                .ok_or(Error::ConfigError(format!("{key} is not a table")))?;
+        }
+        Ok(table)
+    }
+}
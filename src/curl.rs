@@ -0,0 +1,304 @@
+use crate::{Error, RequestTemplate};
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Read},
+};
+use url::Url;
+
+// SWITCHES are the short options that do not take a value
+const SWITCHES: &str = "kIsSLivgf";
+
+// Curl is a request parsed from a curl command line. The parts that make up the request are
+// held in a template, the rest are settings for the client.
+#[derive(Debug, Default, Clone)]
+pub struct Curl {
+    pub template: RequestTemplate,
+    pub insecure: bool,
+    pub cacert: Option<String>,
+}
+
+impl Curl {
+    // parse parses a curl command line, eg one copied from the browser devtools. The leading
+    // `curl` is optional.
+    pub fn parse(command: &str) -> Result<Curl, Error> {
+        let args = shell_words::split(command)
+            .map_err(|err| Error::InvalidArguments(format!("invalid curl command: {err}")))?;
+        Self::from_args(&args)
+    }
+
+    // from_args builds the request from arguments that have already been split by the shell
+    pub fn from_args(args: &[String]) -> Result<Curl, Error> {
+        let mut args: VecDeque<String> = args.iter().cloned().collect();
+        if args.front().map(|v| v.as_str()) == Some("curl") {
+            args.pop_front();
+        }
+
+        let mut curl = Curl::default();
+        let mut method = None;
+        let mut url = None;
+        let mut data: Vec<String> = Vec::new();
+
+        while let Some(mut arg) = args.pop_front() {
+            if !arg.starts_with('-') || arg == "-" {
+                url = Some(arg);
+                continue;
+            }
+
+            // switches can be combined with the short options after them, eg -sSL or
+            // -kXPOST, so the first is split off and the rest handled on its own
+            let mut chars = arg.chars().skip(1);
+            if let (Some(short), Some(_)) = (chars.next(), chars.next()) {
+                if SWITCHES.contains(short) {
+                    args.push_front(format!("-{}", &arg[2..]));
+                    arg = format!("-{short}");
+                }
+            }
+
+            // options can be given as `--opt value`, `--opt=value` or `-Xvalue`
+            let arg = arg.as_str();
+            let (flag, attached) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_owned())),
+                _ if !arg.starts_with("--") && arg.len() > 2 => {
+                    (&arg[..2], Some(arg[2..].to_owned()))
+                }
+                _ => (arg, None),
+            };
+
+            let mut value = || -> Result<String, Error> {
+                match attached.clone() {
+                    Some(v) => Ok(v),
+                    None => args.pop_front().ok_or(Error::InvalidArguments(format!(
+                        "curl option {flag} requires a value"
+                    ))),
+                }
+            };
+
+            match flag {
+                "-X" | "--request" => method = Some(value()?),
+                "--url" => url = Some(value()?),
+                "-H" | "--header" => {
+                    let header = value()?;
+                    let (name, value) =
+                        header
+                            .split_once(':')
+                            .ok_or(Error::InvalidArguments(format!(
+                                "{header} is not a valid http header"
+                            )))?;
+                    curl.template
+                        .headers
+                        .insert(name.trim().to_owned(), value.trim().to_owned());
+                }
+                "-A" | "--user-agent" => {
+                    curl.template
+                        .headers
+                        .insert("User-Agent".to_owned(), value()?);
+                }
+                "-b" | "--cookie" => {
+                    curl.template.headers.insert("Cookie".to_owned(), value()?);
+                }
+                "-e" | "--referer" => {
+                    curl.template.headers.insert("Referer".to_owned(), value()?);
+                }
+                "-d" | "--data" | "--data-ascii" => data.push(read_data(&value()?, true)?),
+                "--data-binary" => data.push(read_data(&value()?, false)?),
+                "--data-raw" => data.push(value()?),
+                "-F" | "--form" => {
+                    let field = value()?;
+                    let (name, value) =
+                        field
+                            .split_once('=')
+                            .ok_or(Error::InvalidArguments(format!(
+                                "{field} is not a valid multipart field"
+                            )))?;
+                    curl.template
+                        .multipart
+                        .insert(name.to_owned(), value.to_owned());
+                }
+                "-u" | "--user" => curl.template.basic_auth = Some(value()?),
+                "-k" | "--insecure" => curl.insecure = true,
+                "--cacert" => curl.cacert = Some(value()?),
+                "-I" | "--head" => method = Some("HEAD".to_owned()),
+                // kla always asks for and decompresses compressed responses, and the rest
+                // only change how curl itself behaves, not the request being made.
+                "--compressed" | "-s" | "--silent" | "-S" | "--show-error" | "-L"
+                | "--location" | "-i" | "--include" | "-v" | "--verbose" | "-g" | "--globoff"
+                | "-f" | "--fail" => (),
+                _ => {
+                    return Err(Error::InvalidArguments(format!(
+                        "unsupported curl option {flag}"
+                    )))
+                }
+            }
+        }
+
+        if !data.is_empty() {
+            curl.template.body = Some(data.join("&"));
+
+            // curl sends data as a form unless told otherwise
+            if !curl
+                .template
+                .headers
+                .keys()
+                .any(|k| k.eq_ignore_ascii_case("content-type"))
+            {
+                curl.template.headers.insert(
+                    "Content-Type".to_owned(),
+                    "application/x-www-form-urlencoded".to_owned(),
+                );
+            }
+        }
+
+        // curl adds the boundary to a multipart content type given with -H, reqwest
+        // sets the whole header itself
+        if !curl.template.multipart.is_empty() {
+            curl.template.headers.retain(|name, value| {
+                !name.eq_ignore_ascii_case("content-type")
+                    || !value.starts_with("multipart/form-data")
+            });
+        }
+
+        let has_body = curl.template.body.is_some()
+            || !curl.template.form.is_empty()
+            || !curl.template.multipart.is_empty();
+        curl.template.method =
+            Some(method.unwrap_or_else(|| if has_body { "POST" } else { "GET" }.to_owned()));
+        curl.template.path = Some(url.ok_or(Error::InvalidArguments(
+            "the curl command does not contain a url".to_owned(),
+        ))?);
+
+        Ok(curl)
+    }
+
+    // relative_to returns the template with the url made relative to the url of an
+    // environment, with the query string broken out into the query parameters. This is the
+    // shape we want when saving the request into the config.
+    pub fn relative_to(&self, base: Option<&String>) -> Result<RequestTemplate, Error> {
+        let mut template = self.template.clone();
+        let mut url = Url::parse(template.path.as_deref().unwrap_or_default())?;
+
+        for (name, value) in url.query_pairs() {
+            template.query.insert(name.into_owned(), value.into_owned());
+        }
+        url.set_query(None);
+
+        let url = url.to_string();
+        let path = base
+            .and_then(|base| url.strip_prefix(base.trim_end_matches('/')))
+            .filter(|path| path.is_empty() || path.starts_with('/'))
+            .map(|path| path.to_owned());
+
+        template.path = Some(path.unwrap_or(url));
+        Ok(template)
+    }
+}
+
+// read_data returns the value of a data option, which is read from a file when it starts
+// with @, or stdin for @-. Like curl, -d drops the line breaks from a file while
+// --data-binary keeps it as it is.
+fn read_data(value: &str, strip: bool) -> Result<String, Error> {
+    let content = match value.strip_prefix('@') {
+        Some("-") => {
+            let mut buf = String::new();
+            io::stdin().read_to_string(&mut buf)?;
+            buf
+        }
+        Some(path) => fs::read_to_string(path)
+            .map_err(|err| Error::InvalidArguments(format!("could not read {path}: {err}")))?,
+        None => return Ok(value.to_owned()),
+    };
+
+    match strip {
+        true => Ok(content.replace(['\r', '\n'], "")),
+        false => Ok(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_can_be_combined() {
+        let curl = Curl::parse("curl -sSLk https://example.com").unwrap();
+        assert!(curl.insecure);
+        assert_eq!(curl.template.method.as_deref(), Some("GET"));
+
+        let curl = Curl::parse("curl -skXPUT https://example.com -d a=1").unwrap();
+        assert!(curl.insecure);
+        assert_eq!(curl.template.method.as_deref(), Some("PUT"));
+        assert_eq!(curl.template.body.as_deref(), Some("a=1"));
+    }
+
+    #[test]
+    fn method_headers_and_auth() {
+        let curl = Curl::parse(
+            "curl -X DELETE 'https://example.com/users/1?force=true' -H 'Accept: application/json' --header='X-Trace:  abc' -u jed:pw --user-agent=kla",
+        )
+        .unwrap();
+
+        let template = &curl.template;
+        assert_eq!(template.method.as_deref(), Some("DELETE"));
+        assert_eq!(
+            template.path.as_deref(),
+            Some("https://example.com/users/1?force=true")
+        );
+        assert_eq!(template.headers["Accept"], "application/json");
+        assert_eq!(template.headers["X-Trace"], "abc");
+        assert_eq!(template.headers["User-Agent"], "kla");
+        assert_eq!(template.basic_auth.as_deref(), Some("jed:pw"));
+
+        let template = curl
+            .relative_to(Some(&"https://example.com/".to_owned()))
+            .unwrap();
+        assert_eq!(template.path.as_deref(), Some("/users/1"));
+        assert_eq!(template.query["force"], "true");
+    }
+
+    #[test]
+    fn data_is_read_from_files() {
+        let path = std::env::temp_dir().join("kla-curl-test");
+        fs::write(&path, "a=1\nb=2\n").unwrap();
+        let path = path.display();
+
+        let curl = Curl::parse(&format!("curl https://example.com -d @{path} -d c=3")).unwrap();
+        assert_eq!(curl.template.method.as_deref(), Some("POST"));
+        assert_eq!(curl.template.body.as_deref(), Some("a=1b=2&c=3"));
+        assert_eq!(
+            curl.template.headers["Content-Type"],
+            "application/x-www-form-urlencoded"
+        );
+
+        let curl = Curl::parse(&format!(
+            "curl https://example.com --data-binary @{path} -H 'content-type: text/plain'"
+        ))
+        .unwrap();
+        assert_eq!(curl.template.body.as_deref(), Some("a=1\nb=2\n"));
+        assert_eq!(curl.template.headers.len(), 1);
+
+        let curl = Curl::parse("curl https://example.com --data-raw @not-a-file -d -x").unwrap();
+        assert_eq!(curl.template.body.as_deref(), Some("@not-a-file&-x"));
+    }
+
+    #[test]
+    fn multipart_fields() {
+        let curl = Curl::parse(
+            "curl https://example.com/upload -H 'Content-Type: multipart/form-data' -F name=jed -F 'avatar=@me.png;type=image/png'",
+        )
+        .unwrap();
+
+        let template = &curl.template;
+        assert_eq!(template.method.as_deref(), Some("POST"));
+        assert!(template.headers.is_empty());
+        assert_eq!(template.multipart["name"], "jed");
+        assert_eq!(template.multipart["avatar"], "@me.png;type=image/png");
+        assert!(Curl::parse("curl https://example.com -F name").is_err());
+    }
+
+    #[test]
+    fn unsupported_options_are_rejected() {
+        assert!(Curl::parse("curl https://example.com --proxy localhost").is_err());
+        assert!(Curl::parse("curl -H").is_err());
+        assert!(Curl::parse("curl -s").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::Error;
+use md5::Md5;
+use rand::Rng;
+use reqwest::header::{HeaderMap, WWW_AUTHENTICATE};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// DigestAuth answers the `WWW-Authenticate: Digest` challenge of a 401 with the username and
+// password, as described by RFC 7616. The MD5 and SHA-256 algorithms are supported, along
+// with their -sess variants, and qop=auth.
+#[derive(Debug, Clone)]
+pub struct DigestAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl DigestAuth {
+    // parse reads the username and password seperated by :, the same as --basic-auth
+    pub fn parse(userpass: &str) -> Result<DigestAuth, Error> {
+        let (username, password) = userpass.split_once(':').ok_or(Error::InvalidArguments(
+            "digest auth must be the username and password seperated by :".to_owned(),
+        ))?;
+        Ok(DigestAuth {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        })
+    }
+
+    // authorization returns the value of the Authorization header answering the challenge
+    // for a request with the method and uri, which is the path and query. `cnonce` is the
+    // nonce picked by the client, see `cnonce`.
+    pub fn authorization(
+        &self,
+        challenge: &str,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+    ) -> Result<String, Error> {
+        let params = params(challenge);
+        let param = |name: &str| params.get(name).map(|v| v.as_str()).unwrap_or_default();
+        let (realm, nonce) = (param("realm"), param("nonce"));
+
+        let algorithm = match param("algorithm") {
+            "" => "MD5",
+            algorithm => algorithm,
+        };
+        let (hash, session): (fn(&str) -> String, bool) = match algorithm.to_uppercase().as_str() {
+            "MD5" => (md5, false),
+            "MD5-SESS" => (md5, true),
+            "SHA-256" => (sha256, false),
+            "SHA-256-SESS" => (sha256, true),
+            _ => {
+                return Err(Error::ClientError(format!(
+                    "the digest algorithm {algorithm} is not supported"
+                )))
+            }
+        };
+
+        // auth-int would need the hash of the body, so only auth is offered
+        let qop = match params.get("qop") {
+            Some(qop) if qop.split(',').any(|q| q.trim() == "auth") => Some("auth"),
+            Some(qop) => {
+                return Err(Error::ClientError(format!(
+                    "the digest qop {qop} is not supported"
+                )))
+            }
+            None => None,
+        };
+        let nc = "00000001";
+
+        let mut ha1 = hash(&format!("{}:{realm}:{}", self.username, self.password));
+        if session {
+            ha1 = hash(&format!("{ha1}:{nonce}:{cnonce}"));
+        }
+        let ha2 = hash(&format!("{method}:{uri}"));
+        let response = match qop {
+            Some(qop) => hash(&format!("{ha1}:{nonce}:{nc}:{cnonce}:{qop}:{ha2}")),
+            None => hash(&format!("{ha1}:{nonce}:{ha2}")),
+        };
+
+        let mut authorization = format!(
+            r#"Digest username="{}", realm="{realm}", nonce="{nonce}", uri="{uri}", algorithm={algorithm}, response="{response}""#,
+            self.username
+        );
+        if let Some(opaque) = params.get("opaque") {
+            authorization.push_str(&format!(r#", opaque="{opaque}""#));
+        }
+        if let Some(qop) = qop {
+            authorization.push_str(&format!(r#", qop={qop}, nc={nc}, cnonce="{cnonce}""#));
+        }
+        Ok(authorization)
+    }
+}
+
+// challenge returns the Digest challenge from the response headers. A server may offer one
+// challenge per algorithm, in which case SHA-256 is preferred over MD5.
+pub fn challenge(headers: &HeaderMap) -> Option<String> {
+    let challenges: Vec<&str> = headers
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter(|value| value.len() > 7 && value[..7].eq_ignore_ascii_case("digest "))
+        .collect();
+
+    challenges
+        .iter()
+        .find(|challenge| {
+            params(challenge)
+                .get("algorithm")
+                .is_some_and(|a| a.to_uppercase().starts_with("SHA-256"))
+        })
+        .or(challenges.first())
+        .map(|challenge| challenge.to_string())
+}
+
+// cnonce returns a random nonce for the client's side of the exchange
+pub fn cnonce() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 16]>())
+}
+
+// params reads the comma seperated key=value pairs of the challenge, where the values may be
+// quoted and contain commas of their own
+fn params(challenge: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = challenge
+        .trim()
+        .get(7..)
+        .unwrap_or_default()
+        .trim_start_matches([' ', ',']);
+
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().to_lowercase();
+        let value = value.trim_start();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                (
+                    quoted[..end].to_owned(),
+                    quoted.get(end + 1..).unwrap_or_default(),
+                )
+            }
+            None => {
+                let end = value.find(',').unwrap_or(value.len());
+                (value[..end].trim().to_owned(), &value[end..])
+            }
+        };
+        params.insert(key, value);
+        rest = remaining.trim_start_matches([' ', ',']);
+    }
+    params
+}
+
+fn md5(value: &str) -> String {
+    hex::encode(Md5::digest(value.as_bytes()))
+}
+
+fn sha256(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    // the example from RFC 2617, which RFC 7616 computes the same way
+    const CHALLENGE: &str = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+    const CNONCE: &str = "0a4f113b";
+
+    fn digest() -> DigestAuth {
+        DigestAuth::parse("Mufasa:Circle Of Life").unwrap()
+    }
+
+    fn response(authorization: &str) -> String {
+        params(authorization)["response"].clone()
+    }
+
+    #[test]
+    fn md5() {
+        let authorization = digest()
+            .authorization(CHALLENGE, "GET", "/dir/index.html", CNONCE)
+            .unwrap();
+
+        assert_eq!(
+            authorization,
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", algorithm=MD5, response="6629fae49393a05397450978507c4ef1", opaque="5ccc069c403ebaf9f0171e9517f40e41", qop=auth, nc=00000001, cnonce="0a4f113b""#
+        );
+    }
+
+    #[test]
+    fn algorithms() {
+        for (algorithm, expected) in [
+            (
+                "SHA-256",
+                "5abdd07184ba512a22c53f41470e5eea7dcaa3a93a59b630c13dfe0a5dc6e38b",
+            ),
+            ("MD5-sess", "8e3825c57e897f5a0dec6c2d4e5059d0"),
+            (
+                "SHA-256-sess",
+                "b8822e12417cb7750f4e2b8515f0dcf25b7dd26993e80bee1426201446a7f59b",
+            ),
+        ] {
+            let challenge = format!("{CHALLENGE}, algorithm={algorithm}");
+            let authorization = digest()
+                .authorization(&challenge, "GET", "/dir/index.html", CNONCE)
+                .unwrap();
+            assert_eq!(response(&authorization), expected, "{algorithm}");
+            assert!(authorization.contains(&format!("algorithm={algorithm},")));
+        }
+    }
+
+    #[test]
+    fn unsupported() {
+        let digest = digest();
+        let authorize = |challenge: &str| digest.authorization(challenge, "GET", "/", CNONCE);
+
+        assert!(authorize(&format!("{CHALLENGE}, algorithm=SHA-512-256")).is_err());
+        assert!(authorize(r#"Digest realm="r", nonce="n", qop="auth-int""#).is_err());
+    }
+
+    #[test]
+    fn params_with_quoted_commas() {
+        let params = params(
+            r#"Digest realm="a, b=c", qop="auth,auth-int" ,algorithm=SHA-256,nonce="n=1",  stale=false"#,
+        );
+
+        assert_eq!(params["realm"], "a, b=c");
+        assert_eq!(params["qop"], "auth,auth-int");
+        assert_eq!(params["algorithm"], "SHA-256");
+        assert_eq!(params["nonce"], "n=1");
+        assert_eq!(params["stale"], "false");
+        assert_eq!(params.len(), 5);
+    }
+
+    #[test]
+    fn challenge_prefers_sha256() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(challenge(&headers), None);
+
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"r\""),
+        );
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Digest realm=\"r\", algorithm=MD5"),
+        );
+        assert_eq!(
+            challenge(&headers).as_deref(),
+            Some("Digest realm=\"r\", algorithm=MD5")
+        );
+
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("digest realm=\"r\", algorithm=SHA-256"),
+        );
+        assert_eq!(
+            challenge(&headers).as_deref(),
+            Some("digest realm=\"r\", algorithm=SHA-256")
+        );
+    }
+}
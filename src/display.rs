@@ -0,0 +1,77 @@
+use crate::Error;
+use http::Version;
+use reqwest::{header::HeaderMap, Request, Response};
+use std::io::Write;
+
+// version returns the http version the way it shows up on the wire
+pub fn version(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_11 => "HTTP/1.1",
+        Version::HTTP_2 => "HTTP/2.0",
+        Version::HTTP_3 => "HTTP/3.0",
+        _ => "Unknown",
+    }
+}
+
+// write_request writes out the request line, headers and body, each line starting with the
+// prefix.
+pub fn write_request(output: &mut dyn Write, request: &Request, prefix: &str) -> Result<(), Error> {
+    write_request_head(output, request, prefix)?;
+
+    if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+        output.write_all(body)?;
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+// write_request_head writes out the request line and headers, each line starting with the
+// prefix.
+pub fn write_request_head(
+    output: &mut dyn Write,
+    request: &Request,
+    prefix: &str,
+) -> Result<(), Error> {
+    writeln!(
+        output,
+        "{prefix}{} {} {}",
+        request.method(),
+        request.url(),
+        version(request.version())
+    )?;
+
+    write_headers(output, request.headers(), prefix)
+}
+
+// write_response_head writes out the status line and headers, each line starting with the
+// prefix.
+pub fn write_response_head(
+    output: &mut dyn Write,
+    response: &Response,
+    prefix: &str,
+) -> Result<(), Error> {
+    writeln!(
+        output,
+        "{prefix}{} {}",
+        version(response.version()),
+        response.status()
+    )?;
+
+    write_headers(output, response.headers(), prefix)
+}
+
+fn write_headers(output: &mut dyn Write, headers: &HeaderMap, prefix: &str) -> Result<(), Error> {
+    for (name, value) in headers {
+        writeln!(
+            output,
+            "{prefix}{name}: {}",
+            String::from_utf8_lossy(value.as_bytes())
+        )?;
+    }
+    writeln!(output, "{prefix}")?;
+
+    Ok(())
+}
@@ -0,0 +1,255 @@
+use crate::{secret, Error, OAuth2, OpenApi, RequestTemplate, Signing, Workflow};
+use config::{Config, Value, ValueKind};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::OnceLock};
+use tera::Context;
+
+// Environment holds the settings for a single `[environment.<name>]` table in the config.
+// Everything other than the url is a default that sits underneath the arguments passed on
+// the command line.
+//
+//   [environment.github]
+//   url = "https://api.github.com/"
+//   headers = { Accept = "application/vnd.github+json" }
+//   bearer_token = "..."
+//   timeout = "30s"
+//   openapi = "specs/github.yaml"
+//
+//   [environment.github.vars]
+//   owner = "d1ngd0"
+//
+// An environment can extend another, in which case it starts out as a copy of the parent
+// and any values it sets are laid over the top. Tables such as headers and templates are
+// merged key by key.
+//
+//   [environment.github-enterprise]
+//   extends = "github"
+//   url = "https://github.example.com/api/v3/"
+//
+// Rather than a fixed bearer_token, an access token can be fetched with OAuth2 client
+// credentials set under `[environment.<name>.oauth2]`, see `OAuth2`. Requests can also be
+// signed with an HMAC by a recipe under `[environment.<name>.signing]`, see `Signing`.
+//
+// Values can reference secrets rather than holding them directly, see `secret::resolve`.
+// They are resolved as each value is used, so listing the templates of an environment or
+// overriding its bearer_token on the command line does not run its commands.
+//
+//   bearer_token = "${env:GITHUB_TOKEN}"
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Environment {
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub extends: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub query: HashMap<String, String>,
+    #[serde(default)]
+    pub basic_auth: Option<String>,
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    #[serde(default)]
+    pub oauth2: Option<OAuth2>,
+    #[serde(default)]
+    pub aws_sigv4: Option<String>,
+    #[serde(default)]
+    pub signing: Option<Signing>,
+    #[serde(default)]
+    pub timeout: Option<String>,
+    #[serde(default)]
+    pub connect_timeout: Option<String>,
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub certificate: Vec<String>,
+    #[serde(default)]
+    pub agent: Option<String>,
+    #[serde(default)]
+    pub openapi: Option<String>,
+    #[serde(default)]
+    pub vars: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub template: HashMap<String, RequestTemplate>,
+    #[serde(default)]
+    pub workflow: HashMap<String, Workflow>,
+    // the document is loaded the first time it is needed, and kept for the requests after
+    #[serde(skip)]
+    api: OnceLock<OpenApi>,
+}
+
+impl Environment {
+    // new loads the environment. The secrets it references are left as they are until the
+    // values holding them are used.
+    pub fn new(env: &str, config: &Config) -> Result<Environment, Error> {
+        let value = Self::resolve(env, config, &mut Vec::new())?;
+        let mut environment: Environment = value.try_deserialize()?;
+        environment.name = env.to_owned();
+        Ok(environment)
+    }
+
+    // resolve looks up the raw table for the environment, merging it over the top of the
+    // environment it extends. `seen` holds the chain of environments that led here, so we
+    // can stop when an environment ends up extending itself.
+    fn resolve(env: &str, config: &Config, seen: &mut Vec<String>) -> Result<Value, Error> {
+        let child = seen.last().cloned();
+        seen.push(env.to_owned());
+
+        if seen[..seen.len() - 1].iter().any(|e| e == env) {
+            return Err(Error::ConfigError(format!(
+                "environment {env} extends itself: {}",
+                seen.join(" -> ")
+            )));
+        }
+
+        let table = match config.get_table(&format!("environment.{env}")) {
+            Ok(table) => table,
+            Err(config::ConfigError::NotFound(_)) => {
+                return Err(Error::ConfigError(match child {
+                    Some(child) => {
+                        format!("environment {child} extends {env}, which is not defined")
+                    }
+                    None => format!("environment {env} is not defined"),
+                }))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let parent = match table.get("extends") {
+            Some(parent) => Some(parent.clone().into_string()?),
+            None => None,
+        };
+
+        let value = Value::new(None, table);
+        match parent {
+            Some(parent) => Ok(merge(Self::resolve(&parent, config, seen)?, value)),
+            None => Ok(value),
+        }
+    }
+
+    // url returns the url of the environment rendered with the context
+    pub fn url(&self, context: &Context) -> Result<Option<String>, Error> {
+        self.url
+            .as_ref()
+            .map(|url| secret::render(url, context, &self.key("url")))
+            .transpose()
+    }
+
+    // bearer_token returns the bearer token of the environment with its secrets resolved
+    pub fn bearer_token(&self) -> Result<Option<String>, Error> {
+        self.bearer_token
+            .as_ref()
+            .map(|token| secret::resolve(token, &self.key("bearer_token")))
+            .transpose()
+    }
+
+    // basic_auth returns the username and password of the environment with its secrets
+    // resolved
+    pub fn basic_auth(&self) -> Result<Option<String>, Error> {
+        self.basic_auth
+            .as_ref()
+            .map(|userpass| secret::resolve(userpass, &self.key("basic_auth")))
+            .transpose()
+    }
+
+    // proxy returns the proxy of the environment with its secrets resolved, as the url may
+    // hold a password
+    pub fn proxy(&self) -> Result<Option<String>, Error> {
+        self.proxy
+            .as_ref()
+            .map(|proxy| secret::resolve(proxy, &self.key("proxy")))
+            .transpose()
+    }
+
+    // key returns the path to a value of the environment within the config, used to point
+    // at the value when its secret can not be resolved
+    pub fn key(&self, name: &str) -> String {
+        format!("environment.{}.{name}", self.name)
+    }
+
+    // openapi returns the OpenAPI document attached to the environment
+    pub fn openapi(&self) -> Result<&OpenApi, Error> {
+        if let Some(api) = self.api.get() {
+            return Ok(api);
+        }
+
+        let path = self.openapi.as_ref().ok_or(Error::ConfigError(format!(
+            "environment {} does not have an openapi document",
+            self.name
+        )))?;
+        let api = OpenApi::open(path)?;
+        Ok(self.api.get_or_init(|| api))
+    }
+
+    // template returns the template with the given name
+    pub fn template(&self, name: &str) -> Result<RequestTemplate, Error> {
+        self.template
+            .get(name)
+            .cloned()
+            .ok_or(Error::ConfigError(format!(
+                "template {name} is not defined for environment {}",
+                self.name
+            )))
+    }
+
+    // templates returns the name and description of every template, sorted by name
+    pub fn templates(&self) -> Vec<(&String, Option<&String>)> {
+        let mut templates: Vec<(&String, Option<&String>)> = self
+            .template
+            .iter()
+            .map(|(name, template)| (name, template.description.as_ref()))
+            .collect();
+        templates.sort();
+        templates
+    }
+
+    pub fn workflow(&self, name: &str) -> Result<Workflow, Error> {
+        self.workflow
+            .get(name)
+            .cloned()
+            .ok_or(Error::ConfigError(format!(
+                "workflow {name} is not defined for environment {}",
+                self.name
+            )))
+    }
+
+    // workflows returns the name and description of every workflow, sorted by name
+    pub fn workflows(&self) -> Vec<(&String, Option<&String>)> {
+        let mut workflows: Vec<(&String, Option<&String>)> = self
+            .workflow
+            .iter()
+            .map(|(name, workflow)| (name, workflow.description.as_ref()))
+            .collect();
+        workflows.sort();
+        workflows
+    }
+}
+
+// merge lays the child over the top of the parent. Tables are merged key by key, anything
+// else set on the child replaces the value from the parent.
+fn merge(parent: Value, child: Value) -> Value {
+    match (parent.kind, child.kind) {
+        (ValueKind::Table(mut parent), ValueKind::Table(child)) => {
+            for (key, value) in child {
+                let value = match parent.remove(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => value,
+                };
+                parent.insert(key, value);
+            }
+            Value::new(None, parent)
+        }
+        (_, child) => Value::new(None, child),
+    }
+}
+
+// environment returns the environment named by `env`, or an empty environment when no name
+// was given.
+pub fn environment(env: Option<&String>, config: &Config) -> Result<Environment, Error> {
+    match env {
+        Some(env) => Environment::new(env, config),
+        None => Ok(Environment::default()),
+    }
+}
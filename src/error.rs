@@ -20,6 +20,12 @@ pub enum Error {
     InvalidURL,
     #[error("Body not UTF-8")]
     InvalidBody,
+    #[error("Assertion failed")]
+    AssertionFailed(String),
+    #[error("Request failed")]
+    StatusFailed(String),
+    #[error("Request does not match the OpenAPI document")]
+    SpecViolation(String),
 }
 
 impl From<reqwest::header::ToStrError> for Error {
@@ -93,3 +99,15 @@ impl From<url::ParseError> for Error {
         Error::InvalidURL
     }
 }
+
+impl From<toml_edit::TomlError> for Error {
+    fn from(err: toml_edit::TomlError) -> Self {
+        Error::ConfigError(err.to_string())
+    }
+}
+
+impl From<toml_edit::ser::Error> for Error {
+    fn from(err: toml_edit::ser::Error) -> Self {
+        Error::ConfigError(err.to_string())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::Error;
+use regex::Regex;
+use serde_json::Value;
+use tera::{Context, Tera};
+
+// Expectations are checks made against the response once it has been received, so kla can
+// be relied on in scripts. They are evaluated against the same context the output template
+// is rendered with.
+//
+//   kla --expect-status 2xx --expect-header "Content-Type: application/json" \
+//       --expect 'login == "d1ngd0"' /users/d1ngd0
+#[derive(Debug, Default, Clone)]
+pub struct Expectations {
+    status: Vec<String>,
+    headers: Vec<(String, String)>,
+    expressions: Vec<String>,
+    fail: bool,
+}
+
+impl Expectations {
+    pub fn new() -> Expectations {
+        Expectations::default()
+    }
+
+    // opt_status adds the statuses the response may have, either exact (201) or a class
+    // (2xx). The response passes when it matches any of them.
+    pub fn opt_status<'a, T>(mut self, status: Option<T>) -> Result<Self, Error>
+    where
+        T: Iterator<Item = &'a String>,
+    {
+        for status in status.into_iter().flatten() {
+            let valid = status.len() == 3
+                && status
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c.eq_ignore_ascii_case(&'x'));
+            if !valid {
+                return Err(Error::InvalidArguments(format!(
+                    "{status} is not a valid status, expected something like 200 or 2xx"
+                )));
+            }
+            self.status.push(status.to_lowercase());
+        }
+        Ok(self)
+    }
+
+    // opt_headers adds headers the response must have, in the form `Name: value`
+    pub fn opt_headers<'a, T>(mut self, headers: Option<T>) -> Result<Self, Error>
+    where
+        T: Iterator<Item = &'a String>,
+    {
+        for header in headers.into_iter().flatten() {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or(Error::InvalidArguments(format!(
+                    "{header} is not a valid http header"
+                )))?;
+            self.headers
+                .push((name.trim().to_lowercase(), value.trim().to_owned()));
+        }
+        Ok(self)
+    }
+
+    // opt_expressions adds tera expressions that must be true, eg `id == 42`
+    pub fn opt_expressions<'a, T>(mut self, expressions: Option<T>) -> Self
+    where
+        T: Iterator<Item = &'a String>,
+    {
+        self.expressions
+            .extend(expressions.into_iter().flatten().cloned());
+        self
+    }
+
+    // fail makes any response outside of 2xx a failure
+    pub fn fail(mut self, fail: bool) -> Self {
+        self.fail = fail;
+        self
+    }
+
+    // check evaluates every expectation against the response. A failed --fail is reported
+    // as an Error::StatusFailed, anything else as an Error::AssertionFailed listing each
+    // expectation that did not hold.
+    pub fn check(&self, response: &Context) -> Result<(), Error> {
+        let status = response
+            .get("resp_status")
+            .and_then(|status| status.as_str())
+            .unwrap_or_default();
+
+        let mut failures = Vec::new();
+
+        if !self.status.is_empty() && !self.status.iter().any(|s| status_matches(s, status)) {
+            failures.push(format!(
+                "expected status {}\n  actual: {status}",
+                self.status.join(" or ")
+            ));
+        }
+
+        for (name, expected) in self.headers.iter() {
+            let actual = response
+                .get(&format!("resp_headers_{name}"))
+                .and_then(|value| value.as_str());
+            if !actual.is_some_and(|actual| header_matches(expected, actual)) {
+                failures.push(format!(
+                    "expected header {name}: {expected}\n  actual: {}",
+                    actual
+                        .map(|v| format!("{name}: {v}"))
+                        .unwrap_or("none".to_owned())
+                ));
+            }
+        }
+
+        for expression in self.expressions.iter() {
+            if !evaluate(expression, response)? {
+                let mut failure = format!("expected {expression}");
+                for (name, value) in variables(expression, response)? {
+                    failure.push_str(&format!("\n  {name} = {value}"));
+                }
+                failures.push(failure);
+            }
+        }
+
+        if !failures.is_empty() {
+            return Err(Error::AssertionFailed(failures.join("\n")));
+        }
+
+        if self.fail && !status.starts_with('2') {
+            return Err(Error::StatusFailed(format!(
+                "the server responded with {status}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+// status_matches compares a status to a pattern such as 201 or 2xx
+fn status_matches(pattern: &str, status: &str) -> bool {
+    pattern.len() == status.len()
+        && pattern
+            .chars()
+            .zip(status.chars())
+            .all(|(p, s)| p == 'x' || p == s)
+}
+
+// header_matches compares the header value, ignoring parameters such as the charset when
+// the expected value does not have any.
+fn header_matches(expected: &str, actual: &str) -> bool {
+    actual == expected
+        || (!expected.contains(';')
+            && actual.split(';').next().unwrap_or_default().trim() == expected)
+}
+
+// evaluate renders the expression as a tera if statement, reporting whether it was true. An
+// expression that refers to something missing from the response is false.
+fn evaluate(expression: &str, response: &Context) -> Result<bool, Error> {
+    let mut tera = Tera::default();
+    tera.add_raw_template(
+        "expect",
+        &format!("{{% if {expression} %}}true{{% endif %}}"),
+    )
+    .map_err(|_| Error::InvalidArguments(format!("{expression} is not a valid expression")))?;
+
+    Ok(tera
+        .render("expect", response)
+        .is_ok_and(|rendered| rendered == "true"))
+}
+
+// variables returns the values of the variables used in the expression, so a failure shows
+// what the response actually held.
+fn variables(expression: &str, response: &Context) -> Result<Vec<(String, String)>, Error> {
+    // strings and filters such as `| length` are not variables
+    let ignored = Regex::new(r#""[^"]*"|'[^']*'|\|\s*[A-Za-z_][A-Za-z0-9_]*"#)?;
+    let names = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*(\.[A-Za-z0-9_]+)*")?;
+    let keywords = [
+        "and", "or", "not", "in", "is", "true", "false", "True", "False",
+    ];
+
+    let expression = ignored.replace_all(expression, "");
+    let mut variables: Vec<(String, String)> = Vec::new();
+    for name in names.find_iter(&expression).map(|m| m.as_str()) {
+        if keywords.contains(&name) || variables.iter().any(|(n, _)| n == name) {
+            continue;
+        }
+
+        let mut parts = name.split('.');
+        let mut value = response.get(parts.next().unwrap_or_default());
+        for part in parts {
+            value = match value {
+                Some(Value::Object(map)) => map.get(part),
+                Some(Value::Array(items)) => part.parse::<usize>().ok().and_then(|i| items.get(i)),
+                _ => None,
+            };
+        }
+
+        variables.push((
+            name.to_owned(),
+            value
+                .map(|v| v.to_string())
+                .unwrap_or("undefined".to_owned()),
+        ));
+    }
+
+    Ok(variables)
+}
@@ -0,0 +1,195 @@
+use crate::Error;
+use reqwest::{header::ACCEPT_ENCODING, Request};
+use std::io::Write;
+
+// export writes the request out as a command or snippet of code for another tool, so the
+// request can be reproduced by someone who does not have kla installed. The supported
+// formats are curl, httpie, python and js-fetch.
+pub fn export(output: &mut dyn Write, request: &Request, format: &str) -> Result<(), Error> {
+    let body = match request.body().map(|body| body.as_bytes()) {
+        Some(Some(body)) => Some(String::from_utf8_lossy(body).into_owned()),
+        None => None,
+        Some(None) => {
+            return Err(Error::InvalidArguments(
+                "the request body is a stream, which can not be exported".to_owned(),
+            ))
+        }
+    };
+
+    // each tool asks for the encodings it can decode itself, curl when told to with
+    // --compressed, so the encodings kla accepts are left to them
+    let compressed = request.headers().contains_key(ACCEPT_ENCODING);
+    let headers = request
+        .headers()
+        .iter()
+        .filter(|(name, _)| *name != ACCEPT_ENCODING)
+        .map(|(name, value)| {
+            (
+                name.as_str().to_owned(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect::<Vec<(String, String)>>();
+
+    let method = request.method().as_str();
+    let url = request.url().as_str();
+
+    match format {
+        "curl" => curl(output, method, url, &headers, body, compressed),
+        "httpie" => httpie(output, method, url, &headers, body),
+        "python" => python(output, method, url, &headers, body),
+        "js-fetch" => fetch(output, method, url, &headers, body),
+        _ => Err(Error::InvalidArguments(format!(
+            "{format} is not a supported export format"
+        ))),
+    }
+}
+
+fn curl(
+    output: &mut dyn Write,
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: Option<String>,
+    compressed: bool,
+) -> Result<(), Error> {
+    // curl waits for the body of a response to a HEAD request unless it is told to only
+    // ask for the head
+    match method {
+        "HEAD" => write!(output, "curl -I {}", shell_quote(url))?,
+        _ => write!(output, "curl -X {method} {}", shell_quote(url))?,
+    }
+    if compressed {
+        write!(output, " \\\n  --compressed")?;
+    }
+    for (name, value) in headers {
+        write!(
+            output,
+            " \\\n  -H {}",
+            shell_quote(&format!("{name}: {value}"))
+        )?;
+    }
+    if let Some(body) = body {
+        write!(output, " \\\n  --data-raw {}", shell_quote(&body))?;
+    }
+    writeln!(output)?;
+    Ok(())
+}
+
+fn httpie(
+    output: &mut dyn Write,
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: Option<String>,
+) -> Result<(), Error> {
+    write!(output, "http {method} {}", shell_quote(url))?;
+    for (name, value) in headers {
+        write!(output, " \\\n  {}", shell_quote(&format!("{name}:{value}")))?;
+    }
+    if let Some(body) = body {
+        write!(output, " \\\n  --raw {}", shell_quote(&body))?;
+    }
+    writeln!(output)?;
+    Ok(())
+}
+
+fn python(
+    output: &mut dyn Write,
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: Option<String>,
+) -> Result<(), Error> {
+    writeln!(output, "import requests")?;
+    writeln!(output)?;
+    writeln!(output, "response = requests.request(")?;
+    writeln!(output, "    {},", quote(method)?)?;
+    writeln!(output, "    {},", quote(url)?)?;
+    if !headers.is_empty() {
+        writeln!(output, "    headers={{")?;
+        for (name, value) in headers {
+            writeln!(output, "        {}: {},", quote(name)?, quote(value)?)?;
+        }
+        writeln!(output, "    }},")?;
+    }
+    if let Some(body) = body {
+        writeln!(output, "    data={},", quote(&body)?)?;
+    }
+    writeln!(output, ")")?;
+    writeln!(output, "print(response.text)")?;
+    Ok(())
+}
+
+fn fetch(
+    output: &mut dyn Write,
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: Option<String>,
+) -> Result<(), Error> {
+    writeln!(output, "const response = await fetch({}, {{", quote(url)?)?;
+    writeln!(output, "  method: {},", quote(method)?)?;
+    if !headers.is_empty() {
+        writeln!(output, "  headers: {{")?;
+        for (name, value) in headers {
+            writeln!(output, "    {}: {},", quote(name)?, quote(value)?)?;
+        }
+        writeln!(output, "  }},")?;
+    }
+    if let Some(body) = body {
+        writeln!(output, "  body: {},", quote(&body)?)?;
+    }
+    writeln!(output, "}});")?;
+    writeln!(output, "console.log(await response.text());")?;
+    Ok(())
+}
+
+// shell_quote wraps the value in single quotes, which stops the shell from interpreting
+// anything within it. Single quotes themselves have to be closed, escaped and reopened.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+// quote produces a double quoted string literal, JSON strings are valid in both python and
+// javascript.
+fn quote(value: &str) -> Result<String, Error> {
+    Ok(serde_json::to_string(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{header::HeaderValue, Method, Url};
+
+    fn exported(mut request: Request, format: &str) -> String {
+        request
+            .headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        let mut output = Vec::new();
+        export(&mut output, &request, format).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn curl_asks_for_compression_and_only_the_head() {
+        let url = Url::parse("https://example.com/users").unwrap();
+
+        assert_eq!(
+            exported(Request::new(Method::HEAD, url.clone()), "curl"),
+            "curl -I 'https://example.com/users' \\\n  --compressed\n"
+        );
+        assert_eq!(
+            exported(Request::new(Method::GET, url), "curl"),
+            "curl -X GET 'https://example.com/users' \\\n  --compressed\n"
+        );
+    }
+
+    #[test]
+    fn encodings_are_left_to_the_tool() {
+        let url = Url::parse("https://example.com/users").unwrap();
+        let request = Request::new(Method::GET, url);
+
+        assert!(!exported(request, "python").contains("gzip"));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{render, Error, RequestTemplate};
+use std::{fs, path::Path};
+use tera::Context;
+
+// HttpFile is a file of requests in the format used by the VS Code REST Client and the
+// JetBrains HTTP Client. Requests are separated by `###`, and variables declared with
+// `@name = value` can be used within any of the requests as `{{name}}`.
+//
+//   @host = https://api.example.com
+//
+//   ### Login
+//   # @name login
+//   POST {{host}}/login HTTP/1.1
+//   Content-Type: application/json
+//
+//   {"username": "jed"}
+#[derive(Debug, Default, Clone)]
+pub struct HttpFile {
+    pub vars: Vec<(String, String)>,
+    pub requests: Vec<HttpRequest>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct HttpRequest {
+    pub name: Option<String>,
+    pub template: RequestTemplate,
+}
+
+const METHODS: [&str; 9] = [
+    "GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS", "TRACE", "CONNECT",
+];
+
+impl HttpFile {
+    pub fn open(path: &str) -> Result<HttpFile, Error> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        Self::parse_in(&fs::read_to_string(path)?, dir).map_err(|err| match err {
+            Error::InvalidArguments(msg) => Error::InvalidArguments(format!("{path}: {msg}")),
+            err => err,
+        })
+    }
+
+    pub fn parse(content: &str) -> Result<HttpFile, Error> {
+        Self::parse_in(content, Path::new(""))
+    }
+
+    // parse_in parses the content of a file in the directory, which the paths of `< file`
+    // bodies are relative to
+    fn parse_in(content: &str, dir: &Path) -> Result<HttpFile, Error> {
+        let mut file = HttpFile::default();
+        let mut block: Vec<(usize, &str)> = Vec::new();
+        let mut title = None;
+
+        for (i, line) in content.lines().enumerate() {
+            if let Some(next_title) = line.strip_prefix("###") {
+                file.parse_block(&block, title, dir)?;
+                block.clear();
+                title = Some(next_title.trim().to_owned()).filter(|t| !t.is_empty());
+                continue;
+            }
+            block.push((i + 1, line));
+        }
+        file.parse_block(&block, title, dir)?;
+
+        Ok(file)
+    }
+
+    // parse_block parses the lines between two `###` separators. Blocks that only declare
+    // variables or hold comments do not add a request.
+    fn parse_block(
+        &mut self,
+        lines: &[(usize, &str)],
+        title: Option<String>,
+        dir: &Path,
+    ) -> Result<(), Error> {
+        let mut lines = lines.iter().peekable();
+        let mut request = HttpRequest::default();
+        request.template.description = title;
+
+        // everything before the request line is variables, comments and blank lines
+        let (number, line) = loop {
+            let Some((number, line)) = lines.next() else {
+                return Ok(());
+            };
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(comment) = line.strip_prefix('#').or(line.strip_prefix("//")) {
+                if let Some(name) = comment.trim().strip_prefix("@name") {
+                    request.name = Some(name.trim().to_owned());
+                }
+                continue;
+            }
+
+            if let Some(var) = line.strip_prefix('@') {
+                let (name, value) = var.split_once('=').ok_or(Error::InvalidArguments(format!(
+                    "line {number}: {line} is not a valid variable"
+                )))?;
+                self.vars
+                    .push((name.trim().to_owned(), value.trim().to_owned()));
+                continue;
+            }
+
+            break (number, line);
+        };
+
+        // the method and http version are optional, the url may contain {{ var }}
+        let (method, url) = match line.split_once(char::is_whitespace) {
+            Some((method, url)) if METHODS.contains(&method) => (method, url.trim()),
+            _ => ("GET", line),
+        };
+        let mut url = url.to_owned();
+
+        // a long query string can be broken over several lines starting with ? or &
+        while let Some((_, line)) = lines.peek() {
+            let line = line.trim();
+            if !line.starts_with('?') && !line.starts_with('&') {
+                break;
+            }
+            url.push_str(line);
+            lines.next();
+        }
+
+        // the version comes last, after any of the query lines
+        let url = match url.rsplit_once(char::is_whitespace) {
+            Some((url, version)) if version.starts_with("HTTP/") => url.trim_end().to_owned(),
+            _ => url,
+        };
+
+        for (number, line) in lines.by_ref() {
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+
+            let (name, value) = line.split_once(':').ok_or(Error::InvalidArguments(format!(
+                "line {number}: {line} is not a valid http header"
+            )))?;
+            request
+                .template
+                .headers
+                .insert(name.trim().to_owned(), value.trim().to_owned());
+        }
+
+        let body = lines
+            .map(|(_, line)| *line)
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let body = body.trim_end();
+
+        if !body.is_empty() {
+            // `< ./file.json` sends the contents of a file as the body
+            match body.strip_prefix("< ") {
+                Some(path) if !path.contains('\n') => {
+                    let path = dir.join(path.trim()).display().to_string();
+                    request.template.body_file = Some(path);
+                }
+                _ => request.template.body = Some(body.to_owned()),
+            }
+        }
+
+        if method.is_empty() || url.is_empty() {
+            return Err(Error::InvalidArguments(format!(
+                "line {number}: {line} is not a valid request line"
+            )));
+        }
+
+        request.template.method = Some(method.to_owned());
+        request.template.path = Some(url);
+        self.requests.push(request);
+
+        Ok(())
+    }
+
+    // context adds the variables declared in the file to the context. The variables are
+    // rendered in order, so they can refer to the variables declared before them.
+    pub fn context(&self, mut context: Context) -> Result<Context, Error> {
+        for (name, value) in self.vars.iter() {
+            let value = render(value, &context)?;
+            context.insert(name, &value);
+        }
+        Ok(context)
+    }
+
+    // request returns the request with the given name, as set by `# @name <name>`
+    pub fn request(&self, name: &str) -> Result<&HttpRequest, Error> {
+        self.requests
+            .iter()
+            .find(|request| request.name.as_deref() == Some(name))
+            .ok_or(Error::InvalidArguments(format!(
+                "there is no request named {name}"
+            )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_split_by_separators() {
+        let file = HttpFile::parse(
+            "@host = https://api.example.com\n@token = {{ host }}/token\n\n### Login\n# @name login\nPOST {{host}}/login HTTP/1.1\nContent-Type: application/json\n\n{\"username\": \"jed\"}\n\n###\n// @name me\n{{host}}/me\n###\n# only a comment\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            file.vars,
+            vec![
+                ("host".to_owned(), "https://api.example.com".to_owned()),
+                ("token".to_owned(), "{{ host }}/token".to_owned()),
+            ]
+        );
+        assert_eq!(file.requests.len(), 2);
+
+        let login = file.request("login").unwrap();
+        assert_eq!(login.template.description.as_deref(), Some("Login"));
+        assert_eq!(login.template.method.as_deref(), Some("POST"));
+        assert_eq!(login.template.path.as_deref(), Some("{{host}}/login"));
+        assert_eq!(login.template.headers["Content-Type"], "application/json");
+        assert_eq!(
+            login.template.body.as_deref(),
+            Some("{\"username\": \"jed\"}")
+        );
+
+        let me = file.request("me").unwrap();
+        assert_eq!(me.template.description, None);
+        assert_eq!(me.template.method.as_deref(), Some("GET"));
+        assert_eq!(me.template.path.as_deref(), Some("{{host}}/me"));
+        assert_eq!(me.template.body, None);
+
+        let context = file.context(Context::new()).unwrap();
+        assert_eq!(
+            context.get("token").and_then(|v| v.as_str()),
+            Some("https://api.example.com/token")
+        );
+    }
+
+    #[test]
+    fn query_can_span_lines() {
+        let file = HttpFile::parse(
+            "GET https://example.com/search\n    ?q=kla\n    &page=2 HTTP/1.1\nAccept: */*\n",
+        )
+        .unwrap();
+
+        let template = &file.requests[0].template;
+        assert_eq!(
+            template.path.as_deref(),
+            Some("https://example.com/search?q=kla&page=2")
+        );
+        assert_eq!(template.headers["Accept"], "*/*");
+    }
+
+    #[test]
+    fn file_bodies_are_relative_to_the_file() {
+        let file = HttpFile::parse_in(
+            "POST https://example.com/upload\n\n< ./data.json\n###\nPOST https://example.com/upload\n\n< /tmp/data.json\n",
+            Path::new("tests"),
+        )
+        .unwrap();
+
+        let files: Vec<Option<&str>> = file
+            .requests
+            .iter()
+            .map(|request| request.template.body_file.as_deref())
+            .collect();
+        assert_eq!(
+            files,
+            vec![Some("tests/./data.json"), Some("/tmp/data.json")]
+        );
+        assert_eq!(file.requests[0].template.body, None);
+    }
+
+    #[test]
+    fn bodies_are_data() {
+        let file = HttpFile::parse(
+            "POST https://example.com/upload\nContent-Type: multipart/form-data; boundary=b\n\n--b\nContent-Disposition: form-data; name=\"a\"\n\n1\n--b--\n###\nPOST https://example.com/mail\n\n@someone\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            file.requests[0].template.body.as_deref(),
+            Some("--b\nContent-Disposition: form-data; name=\"a\"\n\n1\n--b--")
+        );
+        assert_eq!(file.requests[0].template.body_file, None);
+        assert_eq!(file.requests[1].template.body.as_deref(), Some("@someone"));
+    }
+}
@@ -0,0 +1,831 @@
+use crate::{render, Error, RequestTemplate};
+use regex::Regex;
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::{cmp::Ordering, fs, path::Path};
+use tera::Context;
+
+// HurlFile is a scenario written in the Hurl file format. Each entry is a request, optionally
+// followed by the status it should respond with, values to capture for the requests that
+// follow, and assertions on the response.
+//
+//   POST {{host}}/login
+//   {"username": "jed"}
+//
+//   HTTP 200
+//   [Captures]
+//   token: jsonpath "$.token"
+//   [Asserts]
+//   header "Content-Type" contains "json"
+#[derive(Debug, Default, Clone)]
+pub struct HurlFile {
+    pub entries: Vec<HurlEntry>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct HurlEntry {
+    pub line: usize,
+    pub template: RequestTemplate,
+    pub status: Option<u16>,
+    pub captures: Vec<Capture>,
+    pub asserts: Vec<Assert>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub name: String,
+    pub query: Query,
+}
+
+#[derive(Debug, Clone)]
+pub struct Assert {
+    pub line: usize,
+    pub text: String,
+    pub query: Query,
+    pub not: bool,
+    pub predicate: Predicate,
+    pub value: String,
+}
+
+// Query pulls a value out of the response, optionally counting the values it found
+#[derive(Debug, Clone)]
+pub enum Query {
+    Status,
+    Header(String),
+    JsonPath(String),
+    Regex(String),
+    Body,
+    Count(Box<Query>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Predicate {
+    Equals,
+    NotEquals,
+    GreaterThan,
+    GreaterThanOrEquals,
+    LessThan,
+    LessThanOrEquals,
+    StartsWith,
+    EndsWith,
+    Contains,
+    Matches,
+    Exists,
+}
+
+#[derive(PartialEq)]
+enum Section {
+    Request,
+    Query,
+    Form,
+    BasicAuth,
+    Body,
+    // the body is complete, only the status or the next request can follow
+    AfterBody,
+    Response,
+    Captures,
+    Asserts,
+}
+
+const METHODS: [&str; 9] = [
+    "GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS", "TRACE", "CONNECT",
+];
+
+impl HurlFile {
+    pub fn open(path: &str) -> Result<HurlFile, Error> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        Self::parse_in(&fs::read_to_string(path)?, dir).map_err(|err| match err {
+            Error::InvalidArguments(msg) => Error::InvalidArguments(format!("{path}: {msg}")),
+            err => err,
+        })
+    }
+
+    // discover returns the path itself when it is a file, or every .hurl file found under it
+    // when it is a directory, sorted so they always run in the same order.
+    pub fn discover(path: &str) -> Result<Vec<String>, Error> {
+        if !Path::new(path).is_dir() {
+            return Ok(vec![path.to_owned()]);
+        }
+
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            let entry = entry.to_string_lossy();
+            if Path::new(entry.as_ref()).is_dir() {
+                paths.extend(Self::discover(&entry)?);
+            } else if entry.ends_with(".hurl") {
+                paths.push(entry.into_owned());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn parse(content: &str) -> Result<HurlFile, Error> {
+        Self::parse_in(content, Path::new(""))
+    }
+
+    // parse_in parses the content of a file in the directory, which the paths of `file,`
+    // bodies are relative to
+    fn parse_in(content: &str, dir: &Path) -> Result<HurlFile, Error> {
+        let mut file = HurlFile::default();
+        let mut section = Section::Request;
+        let mut body: Vec<&str> = Vec::new();
+        // set while inside a ``` block, where every line is part of the body
+        let mut fenced = false;
+        // tracks the brackets of a json body, which ends when they are all closed
+        let mut json = Nesting::default();
+
+        for (i, raw) in content.lines().enumerate() {
+            let number = i + 1;
+            let line = raw.trim();
+
+            if fenced {
+                if line == "```" {
+                    fenced = false;
+                    file.finish_body(&mut body, dir)?;
+                    section = Section::AfterBody;
+                } else {
+                    body.push(raw);
+                }
+                continue;
+            }
+
+            // a json body is taken as it is, blank lines and all, until it is closed
+            if json.depth > 0 {
+                body.push(raw);
+                json.feed(raw);
+                if json.depth == 0 {
+                    file.finish_body(&mut body, dir)?;
+                    section = Section::AfterBody;
+                }
+                continue;
+            }
+
+            if line.is_empty() || line.starts_with('#') {
+                // blank lines within a text body are part of it
+                if section == Section::Body && !body.is_empty() {
+                    body.push(raw);
+                }
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap_or_default();
+            if METHODS.contains(&first) {
+                // the url is the rest of the line, it may contain {{ var }}
+                let url = line[first.len()..].trim();
+                if url.is_empty() {
+                    return Err(Error::InvalidArguments(format!(
+                        "line {number}: {line} is missing a url"
+                    )));
+                }
+                file.finish_body(&mut body, dir)?;
+                file.entries.push(HurlEntry {
+                    line: number,
+                    template: RequestTemplate {
+                        method: Some(first.to_owned()),
+                        path: Some(url.to_owned()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+                section = Section::Request;
+                continue;
+            }
+
+            let entry = file
+                .entries
+                .last_mut()
+                .ok_or(Error::InvalidArguments(format!(
+                    "line {number}: expected a request, eg GET https://example.com"
+                )))?;
+
+            if first == "HTTP" || first.starts_with("HTTP/") {
+                let status = words.next().ok_or(Error::InvalidArguments(format!(
+                    "line {number}: {line} is missing a status"
+                )))?;
+                entry.status = match status {
+                    "*" => None,
+                    status => Some(status.parse().map_err(|_| {
+                        Error::InvalidArguments(format!(
+                            "line {number}: {status} is not a valid status"
+                        ))
+                    })?),
+                };
+                section = Section::Response;
+                file.finish_body(&mut body, dir)?;
+                continue;
+            }
+
+            // the request sections come before the body, the response sections after the status
+            let in_request = matches!(
+                section,
+                Section::Request | Section::Query | Section::Form | Section::BasicAuth
+            );
+
+            let is_section = line.starts_with('[')
+                && line.ends_with(']')
+                && line[1..line.len() - 1]
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic());
+            if is_section && !matches!(section, Section::Body | Section::AfterBody) {
+                section = match &line[1..line.len() - 1] {
+                    "QueryStringParams" | "Query" if in_request => Section::Query,
+                    "FormParams" | "Form" if in_request => Section::Form,
+                    "BasicAuth" if in_request => Section::BasicAuth,
+                    "Captures" if !in_request => Section::Captures,
+                    "Asserts" if !in_request => Section::Asserts,
+                    name => {
+                        return Err(Error::InvalidArguments(format!(
+                            "line {number}: the [{name}] section is not supported here"
+                        )))
+                    }
+                };
+                continue;
+            }
+
+            // anything in the request that is not a key: value starts the body
+            let is_body = line.starts_with('{')
+                || line.starts_with('[')
+                || line.starts_with('`')
+                || line.starts_with('"')
+                || line.starts_with("file,")
+                || !line.contains(':');
+            if section == Section::AfterBody {
+                return Err(Error::InvalidArguments(format!(
+                    "line {number}: unexpected {line} after the body"
+                )));
+            }
+            if section == Section::Body || (is_body && in_request) {
+                let opening = section != Section::Body;
+                section = Section::Body;
+                if !opening {
+                    body.push(raw);
+                } else if line.starts_with("```") && !line[3..].contains('`') {
+                    // the language hint after ``` is ignored
+                    fenced = true;
+                } else if line.starts_with('{') || line.starts_with('[') {
+                    body.push(raw);
+                    json.feed(raw);
+                    if json.depth == 0 {
+                        file.finish_body(&mut body, dir)?;
+                        section = Section::AfterBody;
+                    }
+                } else if line.starts_with('`')
+                    || line.starts_with('"')
+                    || line.starts_with("file,")
+                {
+                    // the body is on this one line
+                    body.push(raw);
+                    file.finish_body(&mut body, dir)?;
+                    section = Section::AfterBody;
+                } else {
+                    body.push(raw);
+                }
+                continue;
+            }
+
+            match section {
+                Section::Request => {
+                    let (name, value) = key_value(line, number)?;
+                    entry.template.headers.insert(name, value);
+                }
+                Section::Query => {
+                    let (name, value) = key_value(line, number)?;
+                    entry.template.query.insert(name, value);
+                }
+                Section::Form => {
+                    let (name, value) = key_value(line, number)?;
+                    entry.template.form.insert(name, value);
+                }
+                Section::BasicAuth => {
+                    let (user, pass) = key_value(line, number)?;
+                    entry.template.basic_auth = Some(format!("{user}:{pass}"));
+                }
+                // headers listed after the status must match exactly
+                Section::Response => {
+                    let (name, value) = key_value(line, number)?;
+                    entry.asserts.push(Assert {
+                        line: number,
+                        text: line.to_owned(),
+                        query: Query::Header(name),
+                        not: false,
+                        predicate: Predicate::Equals,
+                        value: serde_json::to_string(&value)?,
+                    });
+                }
+                Section::Captures => {
+                    let (name, query) = line.split_once(':').ok_or(Error::InvalidArguments(
+                        format!("line {number}: {line} is not a valid capture"),
+                    ))?;
+                    let (query, rest) = Query::parse(query.trim(), number)?;
+                    if !rest.is_empty() {
+                        return Err(Error::InvalidArguments(format!(
+                            "line {number}: unexpected {rest}"
+                        )));
+                    }
+                    entry.captures.push(Capture {
+                        name: name.trim().to_owned(),
+                        query,
+                    });
+                }
+                Section::Asserts => entry.asserts.push(Assert::parse(line, number)?),
+                Section::Body | Section::AfterBody => unreachable!("the body is handled above"),
+            }
+        }
+
+        if fenced {
+            return Err(Error::InvalidArguments(
+                "a ``` body is never closed".to_owned(),
+            ));
+        }
+        if json.depth > 0 {
+            return Err(Error::InvalidArguments(
+                "a json body is never closed".to_owned(),
+            ));
+        }
+        file.finish_body(&mut body, dir)?;
+
+        Ok(file)
+    }
+
+    // finish_body sets the lines collected so far as the body of the last request
+    fn finish_body(&mut self, body: &mut Vec<&str>, dir: &Path) -> Result<(), Error> {
+        if body.is_empty() {
+            return Ok(());
+        }
+
+        let content = body.join("\n");
+        let content = content.trim();
+        body.clear();
+
+        let Some(entry) = self.entries.last_mut() else {
+            return Ok(());
+        };
+
+        if let Some(path) = content
+            .strip_prefix("file,")
+            .and_then(|path| path.strip_suffix(';'))
+        {
+            entry.template.body_file = Some(dir.join(path.trim()).display().to_string());
+            return Ok(());
+        }
+
+        let content = if let Some(text) = content
+            .strip_prefix("```")
+            .and_then(|text| text.strip_suffix("```"))
+        {
+            text.to_owned()
+        } else if let Some(text) = content
+            .strip_prefix('`')
+            .and_then(|text| text.strip_suffix('`'))
+        {
+            text.to_owned()
+        } else {
+            content.to_owned()
+        };
+
+        entry.template.body = Some(content);
+        Ok(())
+    }
+}
+
+impl HurlEntry {
+    // check compares the response against the expected status and the assertions, failing
+    // with the first one that does not hold.
+    pub fn check(&self, response: &Context) -> Result<(), Error> {
+        if let Some(status) = self.status {
+            let actual = Query::Status.eval(response)?;
+            if actual != Some(Value::from(status)) {
+                return Err(Error::AssertionFailed(format!(
+                    "line {}: expected status {status} but got {}",
+                    self.line,
+                    display(actual.as_ref())
+                )));
+            }
+        }
+
+        for assert in self.asserts.iter() {
+            assert.check(response)?;
+        }
+
+        Ok(())
+    }
+
+    // capture adds the captured values from the response to the context, so they can be used
+    // by the requests that follow.
+    pub fn capture(&self, response: &Context, context: &mut Context) -> Result<(), Error> {
+        for capture in self.captures.iter() {
+            let value = capture
+                .query
+                .eval(response)?
+                .ok_or(Error::AssertionFailed(format!(
+                    "line {}: nothing was captured for {}",
+                    self.line, capture.name
+                )))?;
+            context.insert(&capture.name, &value);
+        }
+        Ok(())
+    }
+}
+
+impl Assert {
+    // parse reads an assertion in the form `<query> [not] <predicate> [value]`
+    pub fn parse(line: &str, number: usize) -> Result<Assert, Error> {
+        let (query, rest) = Query::parse(line, number)?;
+
+        let (not, rest) = match rest.strip_prefix("not ") {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, rest),
+        };
+
+        let (predicate, value) = rest.split_once(' ').unwrap_or((rest, ""));
+        let predicate = match predicate {
+            "==" => Predicate::Equals,
+            "!=" => Predicate::NotEquals,
+            ">" => Predicate::GreaterThan,
+            ">=" => Predicate::GreaterThanOrEquals,
+            "<" => Predicate::LessThan,
+            "<=" => Predicate::LessThanOrEquals,
+            "startsWith" => Predicate::StartsWith,
+            "endsWith" => Predicate::EndsWith,
+            "contains" => Predicate::Contains,
+            "matches" => Predicate::Matches,
+            "exists" => Predicate::Exists,
+            predicate => {
+                return Err(Error::InvalidArguments(format!(
+                    "line {number}: {predicate} is not a supported predicate"
+                )))
+            }
+        };
+
+        let value = value.trim();
+        if value.is_empty() != (predicate == Predicate::Exists) {
+            return Err(Error::InvalidArguments(format!(
+                "line {number}: {line} has the wrong number of values"
+            )));
+        }
+
+        Ok(Assert {
+            line: number,
+            text: line.to_owned(),
+            query,
+            not,
+            predicate,
+            value: value.to_owned(),
+        })
+    }
+
+    // check evaluates the assertion against the response. The expected value is rendered with
+    // the response context first, so it can refer to variables and captures.
+    pub fn check(&self, response: &Context) -> Result<(), Error> {
+        let actual = self.query.eval(response)?;
+        let expected = self.expected(response)?;
+
+        let holds = match (self.predicate, &actual) {
+            (Predicate::Exists, actual) => actual.is_some(),
+            (_, None) => false,
+            (Predicate::Equals, Some(actual)) => equals(actual, &expected),
+            (Predicate::NotEquals, Some(actual)) => !equals(actual, &expected),
+            (Predicate::GreaterThan, Some(actual)) => {
+                compare(actual, &expected) == Some(Ordering::Greater)
+            }
+            (Predicate::GreaterThanOrEquals, Some(actual)) => matches!(
+                compare(actual, &expected),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            (Predicate::LessThan, Some(actual)) => {
+                compare(actual, &expected) == Some(Ordering::Less)
+            }
+            (Predicate::LessThanOrEquals, Some(actual)) => matches!(
+                compare(actual, &expected),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            (Predicate::StartsWith, Some(Value::String(actual))) => {
+                expected.as_str().is_some_and(|e| actual.starts_with(e))
+            }
+            (Predicate::EndsWith, Some(Value::String(actual))) => {
+                expected.as_str().is_some_and(|e| actual.ends_with(e))
+            }
+            (Predicate::Contains, Some(Value::String(actual))) => {
+                expected.as_str().is_some_and(|e| actual.contains(e))
+            }
+            (Predicate::Contains, Some(Value::Array(actual))) => {
+                actual.iter().any(|v| equals(v, &expected))
+            }
+            (Predicate::Matches, Some(Value::String(actual))) => {
+                Regex::new(expected.as_str().unwrap_or_default())?.is_match(actual)
+            }
+            _ => false,
+        };
+
+        if holds == self.not {
+            return Err(Error::AssertionFailed(format!(
+                "line {}: {}\n  actual:   {}\n  expected: {}{}",
+                self.line,
+                self.text,
+                display(actual.as_ref()),
+                if self.not { "not " } else { "" },
+                match self.predicate {
+                    Predicate::Exists => "a value".to_owned(),
+                    _ => display(Some(&expected)),
+                }
+            )));
+        }
+
+        Ok(())
+    }
+
+    // expected parses the value the response is compared against. Strings are double quoted,
+    // and regexes can also be written as /pattern/.
+    fn expected(&self, response: &Context) -> Result<Value, Error> {
+        if self.value.is_empty() {
+            return Ok(Value::Null);
+        }
+
+        let value = render(&self.value, response)?;
+        if let Some(pattern) = value
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            return Ok(Value::String(pattern.replace("\\/", "/")));
+        }
+
+        serde_json::from_str(&value).map_err(|_| {
+            Error::InvalidArguments(format!(
+                "line {}: {value} is not a valid value, strings must be quoted",
+                self.line
+            ))
+        })
+    }
+}
+
+impl Query {
+    // parse reads a query from the start of the text, returning the query and the rest of
+    // the text.
+    pub fn parse(text: &str, number: usize) -> Result<(Query, &str), Error> {
+        let (name, rest) = text.split_once(' ').unwrap_or((text, ""));
+        let rest = rest.trim_start();
+
+        let (query, rest) = match name {
+            "status" => (Query::Status, rest),
+            "body" => (Query::Body, rest),
+            "header" | "jsonpath" | "regex" => {
+                let (arg, rest) = quoted(rest, number)?;
+                let query = match name {
+                    "header" => Query::Header(arg),
+                    "jsonpath" => {
+                        JsonPath::parse(&arg).map_err(|err| {
+                            Error::InvalidArguments(format!(
+                                "line {number}: {arg} is not a valid jsonpath: {err}"
+                            ))
+                        })?;
+                        Query::JsonPath(arg)
+                    }
+                    _ => {
+                        Regex::new(&arg)?;
+                        Query::Regex(arg)
+                    }
+                };
+                (query, rest)
+            }
+            name => {
+                return Err(Error::InvalidArguments(format!(
+                    "line {number}: {name} is not a supported query"
+                )))
+            }
+        };
+
+        match rest.strip_prefix("count") {
+            Some(after) if after.is_empty() || after.starts_with(' ') => {
+                Ok((Query::Count(Box::new(query)), after.trim_start()))
+            }
+            _ => Ok((query, rest)),
+        }
+    }
+
+    // eval pulls the value out of the response context built by Template::send, returning
+    // None when there is nothing to find.
+    pub fn eval(&self, response: &Context) -> Result<Option<Value>, Error> {
+        let body = || {
+            response
+                .get("resp_body")
+                .and_then(|body| body.as_str())
+                .unwrap_or_default()
+        };
+
+        Ok(match self {
+            Query::Status => response
+                .get("resp_status")
+                .and_then(|status| status.as_str())
+                .and_then(|status| status.parse::<u16>().ok())
+                .map(Value::from),
+            Query::Header(name) => response
+                .get(&format!("resp_headers_{}", name.to_lowercase()))
+                .cloned(),
+            Query::Body => Some(Value::String(body().to_owned())),
+            Query::JsonPath(path) => {
+                let Ok(json) = serde_json::from_str::<Value>(body()) else {
+                    return Ok(None);
+                };
+                let path = JsonPath::parse(path)
+                    .map_err(|err| Error::InvalidArguments(err.to_string()))?;
+                let nodes = path.query(&json).all();
+                match nodes.len() {
+                    0 => None,
+                    1 => Some(nodes[0].clone()),
+                    _ => Some(Value::Array(nodes.into_iter().cloned().collect())),
+                }
+            }
+            Query::Regex(pattern) => Regex::new(pattern)?.captures(body()).map(|captures| {
+                let found = captures.get(1).or(captures.get(0)).unwrap();
+                Value::String(found.as_str().to_owned())
+            }),
+            Query::Count(query) => match query.eval(response)? {
+                Some(Value::Array(values)) => Some(Value::from(values.len())),
+                Some(Value::Object(values)) => Some(Value::from(values.len())),
+                Some(_) => Some(Value::from(1)),
+                None => Some(Value::from(0)),
+            },
+        })
+    }
+}
+
+// Nesting counts the brackets that are open in json, skipping over those within strings
+#[derive(Default)]
+struct Nesting {
+    depth: usize,
+    string: bool,
+    escaped: bool,
+}
+
+impl Nesting {
+    fn feed(&mut self, line: &str) {
+        for c in line.chars() {
+            match c {
+                _ if self.escaped => self.escaped = false,
+                '\\' if self.string => self.escaped = true,
+                '"' => self.string = !self.string,
+                '{' | '[' if !self.string => self.depth += 1,
+                '}' | ']' if !self.string => self.depth = self.depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+    }
+}
+
+// key_value splits a `key: value` line
+fn key_value(line: &str, number: usize) -> Result<(String, String), Error> {
+    let (name, value) = line.split_once(':').ok_or(Error::InvalidArguments(format!(
+        "line {number}: {line} is not a valid key: value"
+    )))?;
+    Ok((name.trim().to_owned(), value.trim().to_owned()))
+}
+
+// quoted reads a double quoted string from the start of the text, returning the unescaped
+// string and the rest of the text.
+fn quoted(text: &str, number: usize) -> Result<(String, &str), Error> {
+    let invalid = || Error::InvalidArguments(format!("line {number}: expected a quoted string"));
+    if !text.starts_with('"') {
+        return Err(invalid());
+    }
+
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => {
+                let value = serde_json::from_str(&text[..=i]).map_err(|_| invalid())?;
+                return Ok((value, text[i + 1..].trim_start()));
+            }
+            _ => escaped = false,
+        }
+    }
+
+    Err(invalid())
+}
+
+// equals compares two values, treating numbers as equal when their values are, so 1 == 1.0
+fn equals(actual: &Value, expected: &Value) -> bool {
+    match (actual.as_f64(), expected.as_f64()) {
+        (Some(a), Some(e)) => a == e,
+        _ => actual == expected,
+    }
+}
+
+fn compare(actual: &Value, expected: &Value) -> Option<Ordering> {
+    match (actual, expected) {
+        (Value::Number(a), Value::Number(e)) => a.as_f64()?.partial_cmp(&e.as_f64()?),
+        (Value::String(a), Value::String(e)) => Some(a.cmp(e)),
+        _ => None,
+    }
+}
+
+fn display(value: Option<&Value>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "none".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(entry: &HurlEntry) -> &str {
+        entry.template.body.as_deref().unwrap_or_default()
+    }
+
+    #[test]
+    fn json_body_keeps_blank_lines_and_method_words() {
+        let file = HurlFile::parse(
+            "POST http://example.com/notes\nContent-Type: application/json\n{\n  \"a\": 1,\n\n  \"text\": \"}\"\n,\"b\": [\nGET something\n]}\n\nHTTP 201\n\nGET http://example.com/notes\nHTTP 200\n",
+        )
+        .unwrap();
+
+        assert_eq!(file.entries.len(), 2);
+        assert_eq!(
+            body(&file.entries[0]),
+            "{\n  \"a\": 1,\n\n  \"text\": \"}\"\n,\"b\": [\nGET something\n]}"
+        );
+        assert_eq!(file.entries[0].status, Some(201));
+        assert_eq!(
+            file.entries[0].template.headers["Content-Type"],
+            "application/json"
+        );
+        assert_eq!(file.entries[1].template.body, None);
+        assert_eq!(file.entries[1].status, Some(200));
+    }
+
+    #[test]
+    fn fenced_body_is_raw() {
+        let file = HurlFile::parse(
+            "POST http://example.com\n```text\nline one\n\n# not a comment\nPUT http://example.com\n```\nHTTP 200\n",
+        )
+        .unwrap();
+
+        assert_eq!(file.entries.len(), 1);
+        assert_eq!(
+            body(&file.entries[0]),
+            "line one\n\n# not a comment\nPUT http://example.com"
+        );
+        assert_eq!(file.entries[0].status, Some(200));
+    }
+
+    #[test]
+    fn one_line_bodies() {
+        let file = HurlFile::parse(
+            "POST http://example.com\n```hello world```\nPOST http://example.com\n`hello`\nPOST http://example.com\nfile,data.bin;\nHTTP 200\n",
+        )
+        .unwrap();
+
+        let bodies: Vec<&str> = file.entries.iter().map(body).collect();
+        assert_eq!(bodies, vec!["hello world", "hello", ""]);
+        assert_eq!(
+            file.entries[2].template.body_file.as_deref(),
+            Some("data.bin")
+        );
+        assert_eq!(file.entries[2].status, Some(200));
+
+        let file = HurlFile::parse_in(
+            "POST http://example.com\nfile,data.bin;\nPOST http://example.com\nfile,/tmp/data.bin;\n",
+            Path::new("tests"),
+        )
+        .unwrap();
+        let files: Vec<&str> = file
+            .entries
+            .iter()
+            .filter_map(|entry| entry.template.body_file.as_deref())
+            .collect();
+        assert_eq!(files, vec!["tests/data.bin", "/tmp/data.bin"]);
+    }
+
+    #[test]
+    fn sections_and_asserts() {
+        let file = HurlFile::parse(
+            "GET http://example.com/search\n[QueryStringParams]\nq: kla\n[BasicAuth]\nuser: pass\n\nHTTP 200\n[Captures]\nid: jsonpath \"$.id\"\n[Asserts]\njsonpath \"$.items\" count >= 1\nheader \"Content-Type\" not contains \"xml\"\n",
+        )
+        .unwrap();
+
+        let entry = &file.entries[0];
+        assert_eq!(entry.template.query["q"], "kla");
+        assert_eq!(entry.template.basic_auth.as_deref(), Some("user:pass"));
+        assert_eq!(entry.captures[0].name, "id");
+        assert_eq!(entry.asserts.len(), 2);
+        assert_eq!(entry.asserts[0].predicate, Predicate::GreaterThanOrEquals);
+        assert!(matches!(entry.asserts[0].query, Query::Count(_)));
+        assert!(entry.asserts[1].not);
+        assert_eq!(entry.asserts[1].predicate, Predicate::Contains);
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(HurlFile::parse("HTTP 200\n").is_err());
+        assert!(HurlFile::parse("GET\n").is_err());
+        assert!(HurlFile::parse("POST http://example.com\n{\"a\": 1\n").is_err());
+        assert!(HurlFile::parse("POST http://example.com\n```\nbody\n").is_err());
+        assert!(HurlFile::parse("POST http://example.com\n{}\nX-Header: 1\n").is_err());
+        assert!(HurlFile::parse("GET http://example.com\n[Asserts]\n").is_err());
+    }
+}
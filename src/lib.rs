@@ -1,24 +1,64 @@
+mod config_file;
+mod curl;
+mod digest_auth;
+mod display;
+mod environment;
 mod error;
+mod expect;
+mod export;
+mod http_file;
+mod hurl;
+mod oauth2;
+mod openapi;
 mod optional_file;
-
+mod report;
+mod request_template;
+mod schema;
+mod secret;
+mod signing;
+mod sigv4;
+mod snapshot;
+mod vars;
+mod workflow;
+
+pub use crate::config_file::ConfigFile;
+pub use crate::curl::Curl;
+pub use crate::digest_auth::DigestAuth;
+pub use crate::environment::{environment, Environment};
 pub use crate::error::Error;
+pub use crate::expect::Expectations;
+pub use crate::http_file::{HttpFile, HttpRequest};
+pub use crate::hurl::{Assert, Capture, HurlEntry, HurlFile, Predicate, Query};
+pub use crate::oauth2::OAuth2;
+pub use crate::openapi::{OpenApi, Operation, Parameter};
 pub use crate::optional_file::OptionalFile;
+pub use crate::report::{Outcome, TestReport, TestResult};
+pub use crate::request_template::{Param, ParamType, RequestTemplate};
+pub use crate::schema::Schema;
+pub use crate::signing::Signing;
+pub use crate::sigv4::{AwsCredentials, AwsSigV4};
+pub use crate::snapshot::Snapshot;
+pub use crate::vars::{render, render_all, variables};
+pub use crate::workflow::{Step, Workflow};
 
-use config::Config;
 use duration_string::DurationString;
 use http::Version;
+use reqwest::multipart::{Form, Part};
 use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
+    header::{HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, RANGE},
     redirect::Policy,
-    Body, Certificate, Client, ClientBuilder, Method, RequestBuilder,
+    tls::TlsInfo,
+    Body, Certificate, Client, ClientBuilder, Method, Request, RequestBuilder, Response,
+    StatusCode,
 };
+use sha2::{Digest, Sha256};
 use std::str::FromStr;
 use std::{
     collections::HashMap,
     fs,
     io::{self, Read},
     path::Path,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 use tera::{Context, Tera};
 
@@ -47,7 +87,7 @@ pub trait KlaClientBuilder {
         userpass: Option<&String>,
     ) -> Result<ClientBuilder, Error>;
 
-    fn connect_timeout(self, timeout: Option<&String>) -> Result<ClientBuilder, Error>;
+    fn opt_connect_timeout(self, timeout: Option<&String>) -> Result<ClientBuilder, Error>;
 
     fn opt_certificate<'a, T>(self, certificates: Option<T>) -> Result<ClientBuilder, Error>
     where
@@ -172,7 +212,7 @@ impl KlaClientBuilder for ClientBuilder {
         Ok(self.proxy(proxy.basic_auth(parts.next().unwrap(), parts.next().unwrap_or_default())))
     }
 
-    fn connect_timeout(self, timeout: Option<&String>) -> Result<ClientBuilder, Error> {
+    fn opt_connect_timeout(self, timeout: Option<&String>) -> Result<ClientBuilder, Error> {
         if let None = timeout {
             return Ok(self);
         }
@@ -226,8 +266,9 @@ impl KlaClient for Client {
             Method::GET
         };
 
+        // absolute urls are left alone, the prefix only applies to paths
         let mut url;
-        if let Some(prefix) = prefix {
+        if let Some(prefix) = prefix.filter(|_| !uri.contains("://")) {
             url = String::from(prefix.trim_end_matches("/"));
             url.push_str(uri);
         } else {
@@ -262,12 +303,37 @@ pub trait KlaRequestBuilder {
     where
         T: Iterator<Item = &'a String>;
 
+    fn opt_multipart<'a, T>(self, multipart: Option<T>) -> Result<RequestBuilder, Error>
+    where
+        T: Iterator<Item = &'a String>;
+
     fn opt_body<'a>(self, body: Option<&str>) -> Result<RequestBuilder, Error>;
 
+    fn opt_data(self, body: Option<&String>) -> RequestBuilder;
+
+    fn opt_body_file(self, path: Option<&String>) -> Result<RequestBuilder, Error>;
+
     fn opt_basic_auth(self, userpass: Option<&String>) -> RequestBuilder;
 
     fn opt_bearer_auth(self, token: Option<&String>) -> RequestBuilder;
 
+    fn opt_digest_auth(
+        self,
+        userpass: Option<&String>,
+        challenge: Option<&str>,
+    ) -> Result<RequestBuilder, Error>;
+
+    fn opt_signing(
+        self,
+        signing: Option<&Signing>,
+        context: &Context,
+        key: &str,
+    ) -> Result<RequestBuilder, Error>;
+
+    fn opt_aws_sigv4(self, sigv4: Option<&String>) -> Result<RequestBuilder, Error>;
+
+    fn default_headers(self, headers: &HeaderMap) -> Result<RequestBuilder, Error>;
+
     fn opt_timeout(self, timeout: Option<&String>) -> Result<RequestBuilder, Error>;
 
     fn opt_version(self, version: Option<&String>) -> Result<RequestBuilder, Error>;
@@ -327,6 +393,88 @@ impl KlaRequestBuilder for RequestBuilder {
         self.bearer_auth(token.unwrap())
     }
 
+    // opt_digest_auth answers the Digest challenge from a 401 with the username and password
+    // seperated by :. Without a challenge there is nothing to answer, so the request is left
+    // as it is and the challenge is picked up when it is sent, see `Template::send`.
+    fn opt_digest_auth(
+        self,
+        userpass: Option<&String>,
+        challenge: Option<&str>,
+    ) -> Result<RequestBuilder, Error> {
+        let (Some(userpass), Some(challenge)) = (userpass, challenge) else {
+            return Ok(self);
+        };
+        let digest = DigestAuth::parse(userpass)?;
+
+        let (client, request) = self.build_split();
+        let mut request = request?;
+        let url = request.url();
+        let uri = match url.query() {
+            Some(query) => format!("{}?{query}", url.path()),
+            None => url.path().to_owned(),
+        };
+        let authorization = digest.authorization(
+            challenge,
+            request.method().as_str(),
+            &uri,
+            &digest_auth::cnonce(),
+        )?;
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+        Ok(RequestBuilder::from_parts(client, request))
+    }
+
+    // opt_signing signs the request with the environment's HMAC signing recipe, rendering its
+    // templates with the context. Like opt_aws_sigv4 the signature covers the whole request,
+    // so this must come after the body and headers are set.
+    fn opt_signing(
+        self,
+        signing: Option<&Signing>,
+        context: &Context,
+        key: &str,
+    ) -> Result<RequestBuilder, Error> {
+        let Some(signing) = signing else {
+            return Ok(self);
+        };
+
+        let (client, request) = self.build_split();
+        let mut request = request?;
+        signing.sign(&mut request, context, SystemTime::now(), key)?;
+        Ok(RequestBuilder::from_parts(client, request))
+    }
+
+    // default_headers adds the headers the client would add as it sends the request, such as
+    // the User-Agent, unless the request already has them. Adding them up front means they
+    // are shown by --dry, the exports and -v, and are covered by the signatures.
+    fn default_headers(self, headers: &HeaderMap) -> Result<RequestBuilder, Error> {
+        let (client, request) = self.build_split();
+        let mut request = request?;
+        for (name, value) in headers.iter() {
+            // like reqwest, a compressed part of a response is not asked for
+            let range = name == ACCEPT_ENCODING && request.headers().contains_key(RANGE);
+            if !range && !request.headers().contains_key(name) {
+                request.headers_mut().insert(name, value.clone());
+            }
+        }
+        Ok(RequestBuilder::from_parts(client, request))
+    }
+
+    // opt_aws_sigv4 signs the request for the service:region with AWS Signature Version 4.
+    // The signature covers the whole request, so this must come after everything else.
+    fn opt_aws_sigv4(self, sigv4: Option<&String>) -> Result<RequestBuilder, Error> {
+        let Some(sigv4) = sigv4 else {
+            return Ok(self);
+        };
+        let sigv4 = AwsSigV4::parse(sigv4)?;
+        let credentials = AwsCredentials::load()?;
+
+        let (client, request) = self.build_split();
+        let mut request = request?;
+        sigv4.sign(&mut request, &credentials, SystemTime::now())?;
+        Ok(RequestBuilder::from_parts(client, request))
+    }
+
     fn opt_body<'a>(self, body: Option<&'a str>) -> Result<RequestBuilder, Error> {
         if let None = body {
             return Ok(self);
@@ -353,6 +501,23 @@ impl KlaRequestBuilder for RequestBuilder {
         Ok(self.body(body))
     }
 
+    // opt_data sends the body as it is. Unlike opt_body, which takes the body argument from
+    // the command line, a leading @ or - is part of the data, eg a multipart boundary.
+    fn opt_data(self, body: Option<&String>) -> RequestBuilder {
+        match body {
+            Some(body) => self.body(body.clone()),
+            None => self,
+        }
+    }
+
+    // opt_body_file sends the contents of the file as the body, byte for byte
+    fn opt_body_file(self, path: Option<&String>) -> Result<RequestBuilder, Error> {
+        match path {
+            Some(path) => Ok(self.body(fs::read(path)?)),
+            None => Ok(self),
+        }
+    }
+
     fn opt_query<'a, T>(self, query: Option<T>) -> Result<RequestBuilder, Error>
     where
         T: Iterator<Item = &'a String>,
@@ -422,6 +587,29 @@ impl KlaRequestBuilder for RequestBuilder {
         Ok(self.form(&map))
     }
 
+    // opt_multipart sends the fields as a multipart form, each given as name=value in the
+    // syntax of curl's -F
+    fn opt_multipart<'a, T>(self, multipart: Option<T>) -> Result<RequestBuilder, Error>
+    where
+        T: Iterator<Item = &'a String>,
+    {
+        let Some(multipart) = multipart else {
+            return Ok(self);
+        };
+
+        let mut form = Form::new();
+        for field in multipart {
+            let (name, value) = field
+                .split_once('=')
+                .ok_or(Error::InvalidArguments(format!(
+                    "{field} is not a valid key=value"
+                )))?;
+            form = form.part(name.trim().to_owned(), part(value)?);
+        }
+
+        Ok(self.multipart(form))
+    }
+
     fn opt_headers<'a, T>(self, headers: Option<T>) -> Result<RequestBuilder, Error>
     where
         T: Iterator<Item = &'a String>,
@@ -461,24 +649,16 @@ impl KlaRequestBuilder for RequestBuilder {
     }
 }
 
-pub fn environment(env: Option<&String>, config: &Config) -> Option<String> {
-    if let None = env {
-        return None;
-    }
-    let env = env.unwrap();
-
-    match config.get_string(format!("environment.{}.url", env).as_str()) {
-        Ok(v) => Some(v),
-        Err(_) => None,
-    }
-}
-
 pub struct TemplateBuilder {
     template: Option<Tera>,
     failure_template: Option<Tera>,
     request: Option<RequestBuilder>,
     context: Option<Context>,
     output: Box<dyn std::io::Write>,
+    verbose: u8,
+    oauth2: Option<(OAuth2, String)>,
+    digest_auth: Option<String>,
+    signing: Option<(Signing, String)>,
 }
 
 impl TemplateBuilder {
@@ -489,6 +669,10 @@ impl TemplateBuilder {
             request: None,
             context: None,
             output,
+            verbose: 0,
+            oauth2: None,
+            digest_auth: None,
+            signing: None,
         }
     }
 
@@ -507,6 +691,10 @@ impl TemplateBuilder {
             request: None,
             context: None,
             output: Box::new(std::io::stdout()),
+            verbose: 0,
+            oauth2: None,
+            digest_auth: None,
+            signing: None,
         }
     }
 
@@ -518,6 +706,10 @@ impl TemplateBuilder {
             request: None,
             context: None,
             output: Box::new(file),
+            verbose: 0,
+            oauth2: None,
+            digest_auth: None,
+            signing: None,
         })
     }
 
@@ -528,6 +720,10 @@ impl TemplateBuilder {
             request: None,
             context: None,
             output: Box::new(std::io::Cursor::new(Vec::new())),
+            verbose: 0,
+            oauth2: None,
+            digest_auth: None,
+            signing: None,
         }
     }
 
@@ -567,6 +763,42 @@ impl TemplateBuilder {
         self
     }
 
+    // context sets the values available to the templates in addition to the ones pulled
+    // from the response.
+    pub fn context(mut self, context: Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    // verbose sets how much is written to stderr while sending the request. At 1 the
+    // request and response headers are written, at 2 connection details are added.
+    pub fn verbose(mut self, verbose: u8) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    // opt_oauth2 sets the oauth2 credentials the bearer token of the request came from, for
+    // the environment named env. When the token is rejected with a 401 a new one is fetched
+    // and the request is sent once more.
+    pub fn opt_oauth2(mut self, oauth2: Option<&OAuth2>, env: &str) -> Self {
+        self.oauth2 = oauth2.map(|oauth2| (oauth2.clone(), env.to_owned()));
+        self
+    }
+
+    // opt_digest_auth sets the username and password, seperated by :, used to answer the
+    // Digest challenge when the request is turned away with a 401.
+    pub fn opt_digest_auth(mut self, userpass: Option<&String>) -> Self {
+        self.digest_auth = userpass.cloned();
+        self
+    }
+
+    // opt_signing sets the HMAC signing recipe the request was signed with, found at key in
+    // the config, so the request can be signed again when it is retried with new credentials.
+    pub fn opt_signing(mut self, signing: Option<&Signing>, key: &str) -> Self {
+        self.signing = signing.map(|signing| (signing.clone(), key.to_owned()));
+        self
+    }
+
     pub fn build(self) -> Result<Template, Error> {
         Ok(Template {
             template: self.template,
@@ -576,6 +808,10 @@ impl TemplateBuilder {
             ))?,
             output: self.output,
             context: self.context.unwrap_or(Context::new()),
+            verbose: self.verbose,
+            oauth2: self.oauth2,
+            digest_auth: self.digest_auth,
+            signing: self.signing,
         })
     }
 }
@@ -586,19 +822,116 @@ pub struct Template {
     output: Box<dyn std::io::Write>,
     request: RequestBuilder,
     context: Context,
+    verbose: u8,
+    oauth2: Option<(OAuth2, String)>,
+    digest_auth: Option<String>,
+    signing: Option<(Signing, String)>,
 }
 
 impl Template {
-    pub async fn send(self) -> Result<(), Error> {
+    // dry builds the request and writes it to the output rather than sending it, so you can
+    // see exactly what would have gone over the wire.
+    pub fn dry(self) -> Result<(), Error> {
+        let Template {
+            mut output,
+            request,
+            ..
+        } = self;
+
+        display::write_request(&mut output, &request.build()?, "")
+    }
+
+    // export builds the request and writes it to the output as a command or snippet of code
+    // in the given format (curl, httpie, python or js-fetch) rather than sending it.
+    pub fn export(self, format: &str) -> Result<(), Error> {
+        let Template {
+            mut output,
+            request,
+            ..
+        } = self;
+
+        export::export(&mut output, &request.build()?, format)
+    }
+
+    // request returns a copy of the request that will be sent
+    pub fn request(&self) -> Result<Request, Error> {
+        Ok(self
+            .request
+            .try_clone()
+            .ok_or(Error::InvalidArguments(
+                "the request body is a stream, so it can not be copied".to_owned(),
+            ))?
+            .build()?)
+    }
+
+    // send sends the request and renders the response into the output. The context the
+    // response was rendered with is returned, so callers can make use of the response.
+    pub async fn send(self) -> Result<Context, Error> {
         let Template {
             template,
             failure_template,
             mut output,
             request,
             mut context,
+            verbose,
+            oauth2,
+            digest_auth,
+            signing,
         } = self;
 
-        let response = request.send().await?;
+        let (client, request) = request.build_split();
+        let request = request?;
+        // a copy is kept to send again with new credentials when the request gets a 401,
+        // either to answer a digest challenge or because the access token was revoked
+        let retry = match oauth2.is_some() || digest_auth.is_some() {
+            true => request.try_clone(),
+            false => None,
+        };
+
+        let mut response = execute(&client, request, verbose).await?;
+        // a stream body can not be copied, so there is nothing to answer the challenge with
+        if response.status() == StatusCode::UNAUTHORIZED
+            && retry.is_none()
+            && digest_auth.is_some()
+            && digest_auth::challenge(response.headers()).is_some()
+        {
+            return Err(Error::ClientError(
+                "the server asked for digest auth, but the request body is a stream which can not be sent again with it".to_owned(),
+            ));
+        }
+        if let (StatusCode::UNAUTHORIZED, Some(mut retry)) = (response.status(), retry) {
+            let challenge = digest_auth::challenge(response.headers());
+            let resend = if let (Some(userpass), Some(challenge)) = (&digest_auth, challenge) {
+                retry = RequestBuilder::from_parts(client.clone(), retry)
+                    .opt_digest_auth(Some(userpass), Some(&challenge))?
+                    .build()?;
+                true
+            } else if let Some((oauth2, env)) = &oauth2 {
+                if verbose > 0 {
+                    eprintln!("* The access token was rejected, fetching a new one");
+                }
+                let token = oauth2.refresh(&client, env).await?;
+                retry.headers_mut().insert(
+                    AUTHORIZATION,
+                    HeaderValue::from_str(&format!("Bearer {token}"))?,
+                );
+                true
+            } else {
+                false
+            };
+
+            if resend {
+                // the signature may cover the old credentials, and its timestamp and nonce
+                // must not be reused, so the request is signed once more
+                let mut builder = RequestBuilder::from_parts(client.clone(), retry);
+                if let Some((signing, key)) = &signing {
+                    builder = builder.opt_signing(Some(signing), &context, key)?;
+                }
+                retry = builder.build()?;
+                response = execute(&client, retry, verbose).await?;
+            }
+        }
+
         context.insert("resp_status", response.status().as_str());
 
         let headers = response.headers();
@@ -606,15 +939,7 @@ impl Template {
             context.insert(&format!("resp_headers_{}", name), &value.to_str()?);
         }
 
-        let version = response.version();
-        match version {
-            Version::HTTP_09 => context.insert("resp_http_version", &"HTTP/0.9"),
-            Version::HTTP_10 => context.insert("resp_http_version", &"HTTP/1.0"),
-            Version::HTTP_11 => context.insert("resp_http_version", &"HTTP/1.1"),
-            Version::HTTP_2 => context.insert("resp_http_version", &"HTTP/2.0"),
-            Version::HTTP_3 => context.insert("resp_http_version", &"HTTP/3.0"),
-            _ => context.insert("resp_http_version", &"Unknown"),
-        }
+        context.insert("resp_http_version", display::version(response.version()));
 
         let template = if response.status().is_success() {
             &template
@@ -623,8 +948,9 @@ impl Template {
         };
 
         let content = response.text().await?;
+        // only objects can be merged into the context, other bodies are left in resp_body
         match serde_json::from_str::<serde_json::Value>(&content) {
-            Ok(v) => context.extend(Context::from_value(v)?),
+            Ok(v) if v.is_object() => context.extend(Context::from_value(v)?),
             _ => (),
         }
         context.insert("resp_body", &content);
@@ -634,6 +960,75 @@ impl Template {
             Some(template) => template.render_to("template", &context, &mut output)?,
         }
 
-        Ok(())
+        Ok(context)
+    }
+}
+
+// execute sends the request, writing the request and response heads to stderr when verbose
+async fn execute(client: &Client, request: Request, verbose: u8) -> Result<Response, Error> {
+    if verbose > 0 {
+        display::write_request_head(&mut io::stderr(), &request, "> ")?;
+    }
+
+    let response = client.execute(request).await?;
+    if verbose > 1 {
+        if let Some(addr) = response.remote_addr() {
+            eprintln!("* Connected to {addr}");
+        }
+        // the client only keeps the certificate when built with tls_info
+        if let Some(certificate) = response
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+        {
+            let fingerprint: Vec<String> = Sha256::digest(certificate)
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect();
+            eprintln!(
+                "* Server certificate SHA-256 fingerprint: {}",
+                fingerprint.join(":")
+            );
+        }
+    }
+    if verbose > 0 {
+        display::write_response_head(&mut io::stderr(), &response, "< ")?;
+    }
+
+    Ok(response)
+}
+
+// part builds a part of a multipart form from a value in the syntax of curl's -F, where a
+// preceding @ uploads a file and < sends the contents of a file as the value. The path can be
+// followed by ;type= and ;filename= to set the content type and name of the upload.
+fn part(value: &str) -> Result<Part, Error> {
+    let (upload, file) = match value.chars().next() {
+        Some('@') => (true, &value[1..]),
+        Some('<') => (false, &value[1..]),
+        _ => return Ok(Part::text(value.to_owned())),
+    };
+
+    let mut options = file.split(';');
+    let path = options.next().unwrap_or_default();
+    let mut part = Part::bytes(
+        fs::read(path)
+            .map_err(|err| Error::InvalidArguments(format!("could not read {path}: {err}")))?,
+    );
+    if let Some(name) = Path::new(path).file_name().filter(|_| upload) {
+        part = part.file_name(name.to_string_lossy().into_owned());
     }
+
+    for option in options {
+        part = match option.trim().split_once('=') {
+            Some(("type", mime)) => part.mime_str(mime)?,
+            Some(("filename", name)) => part.file_name(name.to_owned()),
+            _ => {
+                return Err(Error::InvalidArguments(format!(
+                    "{option} is not a supported option of a multipart field"
+                )))
+            }
+        };
+    }
+
+    Ok(part)
 }
@@ -0,0 +1,401 @@
+use crate::{secret, Error};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use url::Url;
+
+// a token is treated as expired this many seconds early, so it does not run out in flight
+const EXPIRY_MARGIN: u64 = 30;
+// how long `kla login` waits for the browser to come back to the redirect listener
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(300);
+
+// OAuth2 holds the settings used to fetch an access token for an environment. The token is
+// sent as a bearer token, and cached on disk until it expires.
+//
+// With a client_secret, tokens are fetched with the client credentials grant.
+//
+//   [environment.orders.oauth2]
+//   token_url = "https://auth.example.com/oauth2/token"
+//   client_id = "kla"
+//   client_secret = "${env:ORDERS_CLIENT_SECRET}"
+//   scopes = ["orders:read", "orders:write"]
+//
+// With an authorize_url, `kla login` signs in as a user with the authorization code grant
+// and PKCE. The refresh token it is given is used to fetch new access tokens from then on.
+// The redirect goes to a listener on 127.0.0.1, which picks a free port unless
+// redirect_port is set.
+//
+//   [environment.profile.oauth2]
+//   authorize_url = "https://auth.example.com/oauth2/authorize"
+//   token_url = "https://auth.example.com/oauth2/token"
+//   client_id = "kla"
+//   scopes = ["openid", "profile"]
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuth2 {
+    pub token_url: String,
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub authorize_url: Option<String>,
+    #[serde(default)]
+    pub redirect_port: Option<u16>,
+}
+
+// Token is an access token as it is kept in the cache. The client it was issued to is kept
+// with it, so changing the credentials of an environment does not reuse the old token.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Token {
+    token_url: String,
+    client_id: String,
+    scopes: Vec<String>,
+    access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    // seconds since the unix epoch, tokens without an expiry are kept until they are rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+impl Token {
+    fn expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now() + EXPIRY_MARGIN)
+    }
+}
+
+impl OAuth2 {
+    // token returns the cached access token for the environment, fetching a new one when
+    // there is none or it has expired.
+    pub async fn token(&self, client: &Client, env: &str) -> Result<String, Error> {
+        match self.load(env) {
+            Some(token) if !token.expired() => Ok(token.access_token),
+            _ => self.refresh(client, env).await,
+        }
+    }
+
+    // refresh fetches a new access token, replacing the cached one. The refresh token from
+    // `kla login` is used when there is one, otherwise the client credentials are.
+    pub async fn refresh(&self, client: &Client, env: &str) -> Result<String, Error> {
+        if let Some(refresh_token) = self.load(env).and_then(|token| token.refresh_token) {
+            let form = vec![
+                ("grant_type", "refresh_token".to_owned()),
+                ("refresh_token", refresh_token.clone()),
+            ];
+            return self
+                .fetch(client, env, form, Some(refresh_token))
+                .await
+                .map_err(|err| match err {
+                    Error::ClientError(msg) => Error::ClientError(format!(
+                        "{msg}\nrun `kla login -e {env}` to log in again"
+                    )),
+                    err => err,
+                });
+        }
+
+        if self.client_secret.is_none() {
+            return Err(Error::ClientError(format!(
+                "there is no access token for environment {env}, run `kla login -e {env}` first"
+            )));
+        }
+
+        let mut form = vec![("grant_type", "client_credentials".to_owned())];
+        if !self.scopes.is_empty() {
+            form.push(("scope", self.scopes.join(" ")));
+        }
+        self.fetch(client, env, form, None).await
+    }
+
+    // login signs in with the authorization code grant and PKCE. A listener is started on
+    // the loopback interface to catch the redirect, and the authorize url is printed, and
+    // opened in the browser when `open` is set. The tokens are cached for the environment.
+    pub async fn login(&self, client: &Client, env: &str, open: bool) -> Result<(), Error> {
+        let authorize_url = self
+            .authorize_url
+            .as_ref()
+            .ok_or(Error::ConfigError(format!(
+                "environment {env} does not have an oauth2 authorize_url to log in with"
+            )))?;
+
+        let listener = TcpListener::bind(("127.0.0.1", self.redirect_port.unwrap_or(0))).await?;
+        let redirect_uri = format!(
+            "http://127.0.0.1:{}/callback",
+            listener.local_addr()?.port()
+        );
+
+        let verifier = random(64);
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let state = random(32);
+
+        let client_id = secret::resolve(&self.client_id, &key(env, "client_id"))?;
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", &client_id),
+            ("redirect_uri", &redirect_uri),
+            ("state", &state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ];
+        let scope = self.scopes.join(" ");
+        if !scope.is_empty() {
+            params.push(("scope", &scope));
+        }
+        let url = Url::parse_with_params(authorize_url, &params)?;
+
+        eprintln!("open this url to log in to {env}:\n\n  {url}\n");
+        if open {
+            browse(url.as_str());
+        }
+
+        let code = tokio::time::timeout(LOGIN_TIMEOUT, redirect(&listener, &state))
+            .await
+            .map_err(|_| {
+                Error::ClientError(format!(
+                    "gave up waiting to be redirected to {redirect_uri}"
+                ))
+            })??;
+
+        let form = vec![
+            ("grant_type", "authorization_code".to_owned()),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ];
+        self.fetch(client, env, form, None).await?;
+        Ok(())
+    }
+
+    // fetch requests a token from the token endpoint with the form, caching it for the
+    // environment. Confidential clients authenticate with their secret, public clients only
+    // send their id. `refresh_token` is kept when the response does not include a new one.
+    async fn fetch(
+        &self,
+        client: &Client,
+        env: &str,
+        mut form: Vec<(&str, String)>,
+        refresh_token: Option<String>,
+    ) -> Result<String, Error> {
+        let mut request = client.post(&self.token_url);
+        let client_id = secret::resolve(&self.client_id, &key(env, "client_id"))?;
+        match &self.client_secret {
+            Some(client_secret) => {
+                let client_secret = secret::resolve(client_secret, &key(env, "client_secret"))?;
+                request = request.basic_auth(client_id, Some(client_secret));
+            }
+            None => form.push(("client_id", client_id)),
+        }
+
+        let response = request.form(&form).send().await?;
+        let status = response.status();
+        let content = response.text().await?;
+        if !status.is_success() {
+            return Err(Error::ClientError(format!(
+                "could not fetch an access token from {}, it returned {status}: {content}",
+                self.token_url
+            )));
+        }
+
+        let response: TokenResponse = serde_json::from_str(&content).map_err(|err| {
+            Error::ClientError(format!(
+                "the token response from {} is not valid: {err}",
+                self.token_url
+            ))
+        })?;
+
+        let token = Token {
+            token_url: self.token_url.clone(),
+            client_id: self.client_id.clone(),
+            scopes: self.scopes.clone(),
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.or(refresh_token),
+            expires_at: response.expires_in.map(|expires_in| now() + expires_in),
+        };
+        self.save(env, &token)?;
+
+        Ok(token.access_token)
+    }
+
+    // load returns the token in the cache, as long as it was issued to this client. A cache
+    // that can not be read is treated as empty.
+    fn load(&self, env: &str) -> Option<Token> {
+        let token: Token = serde_json::from_str(&fs::read_to_string(cache_path(env)).ok()?).ok()?;
+
+        let issued_here = token.token_url == self.token_url
+            && token.client_id == self.client_id
+            && token.scopes == self.scopes;
+        issued_here.then_some(token)
+    }
+
+    // save writes the token into the cache. The token is as good as a password, so only the
+    // owner may read the file, or list the directory it is in.
+    fn save(&self, env: &str, token: &Token) -> Result<(), Error> {
+        let path = cache_path(env);
+        let content = serde_json::to_string_pretty(token)?;
+
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+            use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+            if let Some(dir) = path.parent() {
+                fs::DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)?;
+                fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+            }
+
+            // the mode only applies to new files, so one left readable is locked down before
+            // the token is written into it
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .mode(0o600)
+                .open(&path)?;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+            file.write_all(content.as_bytes())?;
+        }
+
+        #[cfg(not(unix))]
+        {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&path, content)?;
+        }
+
+        Ok(())
+    }
+}
+
+// redirect waits for the browser to be redirected back to the listener, returning the
+// authorization code. Requests for anything other than the callback, such as a favicon,
+// are turned away.
+async fn redirect(listener: &TcpListener, state: &str) -> Result<String, Error> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut buf = vec![0; 8192];
+        let n = stream.read(&mut buf).await?;
+        let head = String::from_utf8_lossy(&buf[..n]);
+
+        // the request line is `GET /callback?code=...&state=... HTTP/1.1`
+        let target = head.split_whitespace().nth(1).unwrap_or_default();
+        let url = Url::parse(&format!("http://127.0.0.1{target}"))?;
+        if url.path() != "/callback" {
+            respond(&mut stream, "404 Not Found", "").await?;
+            continue;
+        }
+
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+
+        let result = match (param("error"), param("code")) {
+            (Some(error), _) => Err(format!(
+                "the login was refused: {error} {}",
+                param("error_description").unwrap_or_default()
+            )),
+            (None, Some(_)) if param("state").as_deref() != Some(state) => {
+                Err("the state of the redirect does not match the login".to_owned())
+            }
+            (None, Some(code)) => Ok(code),
+            (None, None) => Err("the redirect did not include a code".to_owned()),
+        };
+
+        let message = match &result {
+            Ok(_) => "Logged in, you can close this window and return to kla.".to_owned(),
+            Err(msg) => msg.clone(),
+        };
+        respond(&mut stream, "200 OK", &message).await?;
+
+        return result.map_err(Error::ClientError);
+    }
+}
+
+async fn respond(
+    stream: &mut tokio::net::TcpStream,
+    status: &str,
+    body: &str,
+) -> Result<(), Error> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+// browse opens the url in the default browser. Failing to is fine, the url has already been
+// printed so it can be opened by hand.
+fn browse(url: &str) {
+    let command = if cfg!(target_os = "macos") {
+        Command::new("open").arg(url).spawn()
+    } else if cfg!(windows) {
+        Command::new("cmd").args(["/C", "start", "", url]).spawn()
+    } else {
+        Command::new("xdg-open")
+            .arg(url)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+    };
+    let _ = command;
+}
+
+// random returns a string of random letters and digits, which are safe to use as both the
+// PKCE verifier and the state
+fn random(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+// cache_path is where the token for the environment is kept, under $XDG_CACHE_HOME/kla or
+// ~/.cache/kla when it is not set.
+fn cache_path(env: &str) -> PathBuf {
+    let cache = match env::var("XDG_CACHE_HOME") {
+        Ok(cache) if !cache.is_empty() => PathBuf::from(cache),
+        _ => PathBuf::from(env::var("HOME").unwrap_or_default()).join(".cache"),
+    };
+    cache.join("kla").join("oauth2").join(format!("{env}.json"))
+}
+
+// key returns the path to one of the oauth2 settings of the environment within the config
+fn key(env: &str, name: &str) -> String {
+    format!("environment.{env}.oauth2.{name}")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
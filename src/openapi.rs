@@ -0,0 +1,1047 @@
+use crate::{schema::violation, Error, Param, ParamType, RequestTemplate};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use jsonschema::{Draft, JSONSchema};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use regex::Regex;
+use reqwest::{
+    header::{CONTENT_TYPE, COOKIE},
+    Request,
+};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex},
+};
+use tera::Context;
+use url::Url;
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+// OpenApi is an OpenAPI 3 document attached to an environment. Either YAML or JSON is
+// accepted.
+//
+//   [environment.prod]
+//   url = "https://api.example.com"
+//   openapi = "specs/users.yaml"
+#[derive(Debug, Clone)]
+pub struct OpenApi {
+    pub path: String,
+    pub document: Value,
+    // the schemas that have been compiled, by their source, so the schemas of an operation
+    // are only compiled once however many of its requests are checked
+    compiled: Arc<Mutex<HashMap<String, Arc<JSONSchema>>>>,
+}
+
+// Operation is a single method on a path of the document
+#[derive(Debug, Clone)]
+pub struct Operation {
+    pub id: String,
+    pub method: String,
+    pub path: String,
+    pub summary: Option<String>,
+    pub parameters: Vec<Parameter>,
+    // the schema of the application/json request body
+    pub body: Option<Value>,
+    pub body_required: bool,
+    pub responses: Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub location: String,
+    pub required: bool,
+    pub description: Option<String>,
+    pub schema: Option<Value>,
+}
+
+impl OpenApi {
+    pub fn open(path: &str) -> Result<OpenApi, Error> {
+        let document: Value = serde_yaml::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| Error::ConfigError(format!("{path}: {err}")))?;
+
+        if !document.get("openapi").is_some_and(|v| v.is_string()) {
+            return Err(Error::ConfigError(format!(
+                "{path} is not an OpenAPI 3 document"
+            )));
+        }
+
+        Ok(OpenApi {
+            path: path.to_owned(),
+            document,
+            compiled: Arc::default(),
+        })
+    }
+
+    // resolve follows a local `$ref`, such as #/components/schemas/User, returning the value
+    // itself when it is not a reference.
+    pub fn resolve<'a>(&'a self, value: &'a Value) -> &'a Value {
+        let mut value = value;
+        // a limit rather than tracking what has been seen, references to references are rare
+        for _ in 0..16 {
+            let Some(pointer) = value
+                .get("$ref")
+                .and_then(|r| r.as_str())
+                .and_then(|r| r.strip_prefix('#'))
+            else {
+                break;
+            };
+            match self.document.pointer(pointer) {
+                Some(target) => value = target,
+                None => break,
+            }
+        }
+        value
+    }
+
+    // operations returns every operation in the document that has an operationId, sorted
+    // by the id.
+    pub fn operations(&self) -> Vec<Operation> {
+        let mut operations = Vec::new();
+        let Some(paths) = self.document.get("paths").and_then(|p| p.as_object()) else {
+            return operations;
+        };
+
+        for (path, item) in paths {
+            let item = self.resolve(item);
+            for method in METHODS {
+                if let Some(operation) = item.get(method) {
+                    let operation = self.operation_at(path, method, item, operation);
+                    if !operation.id.is_empty() {
+                        operations.push(operation);
+                    }
+                }
+            }
+        }
+
+        operations.sort_by(|a, b| a.id.cmp(&b.id));
+        operations
+    }
+
+    pub fn operation(&self, id: &str) -> Result<Operation, Error> {
+        self.operations()
+            .into_iter()
+            .find(|operation| operation.id == id)
+            .ok_or(Error::InvalidArguments(format!(
+                "there is no operation {id} in {}",
+                self.path
+            )))
+    }
+
+    // servers returns the urls from the servers list, with any variables set to their
+    // defaults.
+    pub fn servers(&self) -> Vec<(String, Option<String>)> {
+        let Some(servers) = self.document.get("servers").and_then(|s| s.as_array()) else {
+            return Vec::new();
+        };
+
+        servers
+            .iter()
+            .filter_map(|server| {
+                let mut url = server.get("url")?.as_str()?.to_owned();
+                if let Some(variables) = server.get("variables").and_then(|v| v.as_object()) {
+                    for (name, variable) in variables {
+                        let default = variable
+                            .get("default")
+                            .and_then(|d| d.as_str())
+                            .unwrap_or_default();
+                        url = url.replace(&format!("{{{name}}}"), default);
+                    }
+                }
+                let description = server
+                    .get("description")
+                    .and_then(|d| d.as_str())
+                    .map(|d| d.to_owned());
+                Some((url, description))
+            })
+            .collect()
+    }
+
+    // validate_request checks the request before it is sent. The method and path must be
+    // documented, the required parameters must be present, and the parameters and JSON body
+    // must match their schemas.
+    pub fn validate_request(&self, request: &Request) -> Result<(), Error> {
+        let (operation, path_values) = self.find(request)?;
+        let mut violations = Vec::new();
+
+        let query: Vec<(String, String)> = request.url().query_pairs().into_owned().collect();
+        let cookies: Vec<(String, String)> = request
+            .headers()
+            .get_all(COOKIE)
+            .iter()
+            .filter_map(|cookies| cookies.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        let find = |pairs: &[(String, String)], name: &str| {
+            pairs
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        for parameter in operation.parameters.iter() {
+            let value = match parameter.location.as_str() {
+                "path" => path_values.get(&parameter.name).cloned(),
+                "query" => find(&query, &parameter.name),
+                "cookie" => find(&cookies, &parameter.name),
+                "header" => request
+                    .headers()
+                    .get(parameter.name.as_str())
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_owned()),
+                _ => continue,
+            };
+
+            let Some(value) = value else {
+                if parameter.required {
+                    violations.push(format!(
+                        "missing the required {} parameter {}",
+                        parameter.location, parameter.name
+                    ));
+                }
+                continue;
+            };
+
+            if let Some(schema) = &parameter.schema {
+                let subject = format!("{} parameter {}", parameter.location, parameter.name);
+                violations.extend(self.check(&subject, schema, &coerce(&value, schema))?);
+            }
+        }
+
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .filter(|body| !body.is_empty());
+        let json = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.contains("json"))
+            .unwrap_or(true);
+        match (body, &operation.body) {
+            (None, _) if operation.body_required => {
+                violations.push("missing the required request body".to_owned())
+            }
+            (Some(body), Some(schema)) if json => match serde_json::from_slice(body) {
+                Ok(body) => violations.extend(self.check("body", schema, &body)?),
+                Err(_) => violations.push("the request body is not JSON".to_owned()),
+            },
+            _ => (),
+        }
+
+        self.violated(
+            format!(
+                "the request {} {} does not match {} {} in {}",
+                request.method(),
+                request.url().path(),
+                operation.method,
+                operation.path,
+                self.path
+            ),
+            violations,
+        )
+    }
+
+    // validate_response checks the response to the request, built by Template::send. The
+    // status must be documented, and the body must match the schema for that status.
+    pub fn validate_response(&self, request: &Request, response: &Context) -> Result<(), Error> {
+        let (operation, _) = self.find(request)?;
+        let mut violations = Vec::new();
+
+        let status = response
+            .get("resp_status")
+            .and_then(|status| status.as_str())
+            .unwrap_or_default();
+        let responses = operation.responses.as_object().cloned().unwrap_or_default();
+        // an exact status wins over a range such as 2XX, which wins over the default
+        let documented = responses
+            .get(status)
+            .or_else(|| {
+                responses.iter().find_map(|(code, documented)| {
+                    (code.len() == 3
+                        && code[1..].eq_ignore_ascii_case("xx")
+                        && status.starts_with(&code[..1]))
+                    .then_some(documented)
+                })
+            })
+            .or(responses.get("default"));
+
+        match documented.map(|documented| self.resolve(documented)) {
+            None => violations.push(format!(
+                "the status {status} is not documented, expected one of {}",
+                responses
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )),
+            Some(documented) => {
+                let schema = documented
+                    .get("content")
+                    .and_then(|content| content.as_object())
+                    .and_then(|content| {
+                        content.get("application/json").or(content
+                            .iter()
+                            .find(|(t, _)| t.contains("json"))
+                            .map(|c| c.1))
+                    })
+                    .and_then(|media| media.get("schema"))
+                    .map(|schema| self.resolve(schema));
+
+                if let Some(schema) = schema {
+                    let content = response
+                        .get("resp_body")
+                        .and_then(|body| body.as_str())
+                        .unwrap_or_default();
+                    match serde_json::from_str(content) {
+                        Ok(body) => violations.extend(self.check("body", schema, &body)?),
+                        Err(_) => violations.push("the response body is not JSON".to_owned()),
+                    }
+                }
+            }
+        }
+
+        self.violated(
+            format!(
+                "the response to {} {} does not match {} {} in {}",
+                request.method(),
+                request.url().path(),
+                operation.method,
+                operation.path,
+                self.path
+            ),
+            violations,
+        )
+    }
+
+    // find returns the operation the request is for, along with the values of the path
+    // parameters. The path of each server is stripped from the request first, so the paths
+    // in the document can be matched.
+    fn find(&self, request: &Request) -> Result<(Operation, HashMap<String, String>), Error> {
+        let method = request.method().as_str().to_lowercase();
+        let path = request.url().path();
+        let undocumented = |msg: String| {
+            Error::SpecViolation(format!(
+                "the request {} {path} does not match {}\n  {msg}",
+                request.method(),
+                self.path
+            ))
+        };
+
+        let mut candidates: Vec<&str> = self
+            .servers()
+            .iter()
+            .filter_map(|(url, _)| {
+                let base = Url::parse(url).map_or(url.clone(), |url| url.path().to_owned());
+                let base = base.trim_end_matches('/');
+                (!base.is_empty())
+                    .then(|| path.strip_prefix(base))
+                    .flatten()
+            })
+            .collect();
+        candidates.push(path);
+
+        let paths = self
+            .document
+            .get("paths")
+            .and_then(|p| p.as_object())
+            .cloned()
+            .unwrap_or_default();
+        let mut matched: Vec<(&String, &Value, HashMap<String, String>)> = Vec::new();
+        for (template, item) in paths.iter() {
+            if let Some(values) = candidates.iter().find_map(|c| path_values(template, c)) {
+                matched.push((template, self.resolve(item), values));
+            }
+        }
+        // literal segments are more specific than parameters, so /users/me wins over
+        // /users/{id}
+        matched.sort_by_key(|(_, _, values)| values.len());
+
+        if matched.is_empty() {
+            return Err(undocumented("the path is not documented".to_owned()));
+        }
+
+        for (template, item, values) in matched.iter() {
+            if let Some(operation) = item.get(&method) {
+                return Ok((
+                    self.operation_at(template, &method, item, operation),
+                    values.clone(),
+                ));
+            }
+        }
+
+        let allowed: Vec<String> = METHODS
+            .iter()
+            .filter(|m| matched.iter().any(|(_, item, _)| item.get(m).is_some()))
+            .map(|m| m.to_uppercase())
+            .collect();
+        Err(undocumented(format!(
+            "{} is not documented for {}, expected one of {}",
+            request.method(),
+            matched[0].0,
+            allowed.join(", ")
+        )))
+    }
+
+    // check validates the value against the schema, returning a line for each violation.
+    // The components of the document are copied into the schema so references resolve.
+    fn check(&self, subject: &str, schema: &Value, value: &Value) -> Result<Vec<String>, Error> {
+        let compiled = self.compile(schema)?;
+
+        let violations = match compiled.validate(value) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors.map(|error| violation(subject, &error)).collect(),
+        };
+        Ok(violations)
+    }
+
+    // compile compiles the schema, or returns it from the cache when it has been already
+    fn compile(&self, schema: &Value) -> Result<Arc<JSONSchema>, Error> {
+        let key = schema.to_string();
+        let mut compiled = self.compiled.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(schema) = compiled.get(&key) {
+            return Ok(schema.clone());
+        }
+
+        let mut schema = schema.clone();
+        if let (Value::Object(schema), Some(components)) =
+            (&mut schema, self.document.get("components"))
+        {
+            schema.insert("components".to_owned(), components.clone());
+        }
+
+        // 3.0 documents use an extended subset of draft 4, 3.1 documents use 2020-12
+        let draft = match self.document.get("openapi").and_then(|v| v.as_str()) {
+            Some(version) if version.starts_with("3.0") => {
+                nullable(&mut schema);
+                Draft::Draft4
+            }
+            _ => Draft::Draft202012,
+        };
+        let schema = Arc::new(
+            JSONSchema::options()
+                .with_draft(draft)
+                .compile(&schema)
+                .map_err(|err| Error::ConfigError(format!("{}: {err}", self.path)))?,
+        );
+
+        compiled.insert(key, schema.clone());
+        Ok(schema)
+    }
+
+    fn violated(&self, summary: String, violations: Vec<String>) -> Result<(), Error> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = vec![summary];
+        lines.extend(violations.into_iter().map(|v| format!("  {v}")));
+        Err(Error::SpecViolation(lines.join("\n")))
+    }
+
+    fn operation_at(&self, path: &str, method: &str, item: &Value, operation: &Value) -> Operation {
+        // operations without an id can still be found by their method and path
+        let id = operation
+            .get("operationId")
+            .and_then(|id| id.as_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        // parameters on the operation replace the ones with the same name on the path
+        let mut parameters: Vec<Parameter> = Vec::new();
+        let declared = [item.get("parameters"), operation.get("parameters")];
+        for parameter in declared
+            .into_iter()
+            .flatten()
+            .filter_map(|p| p.as_array())
+            .flatten()
+        {
+            let parameter = self.resolve(parameter);
+            let (Some(name), Some(location)) = (
+                parameter.get("name").and_then(|n| n.as_str()),
+                parameter.get("in").and_then(|n| n.as_str()),
+            ) else {
+                continue;
+            };
+
+            parameters.retain(|p| p.name != name || p.location != location);
+            parameters.push(Parameter {
+                name: name.to_owned(),
+                location: location.to_owned(),
+                required: location == "path"
+                    || parameter
+                        .get("required")
+                        .and_then(|r| r.as_bool())
+                        .unwrap_or_default(),
+                description: parameter
+                    .get("description")
+                    .and_then(|d| d.as_str())
+                    .map(|d| d.to_owned()),
+                schema: parameter.get("schema").map(|s| self.resolve(s).clone()),
+            });
+        }
+
+        let request_body = operation.get("requestBody").map(|b| self.resolve(b));
+        let body = request_body
+            .and_then(|b| b.pointer("/content/application~1json/schema"))
+            .map(|s| self.resolve(s).clone());
+
+        Operation {
+            id,
+            method: method.to_uppercase(),
+            path: path.to_owned(),
+            summary: operation
+                .get("summary")
+                .or(operation.get("description"))
+                .and_then(|s| s.as_str())
+                .map(|s| s.to_owned()),
+            parameters,
+            body,
+            body_required: request_body
+                .and_then(|b| b.get("required"))
+                .and_then(|r| r.as_bool())
+                .unwrap_or_default(),
+            responses: operation.get("responses").cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl Operation {
+    // properties returns the top level properties of the JSON request body, which are
+    // exposed as flags unless a parameter already has the name.
+    fn properties<'a>(&'a self, api: &'a OpenApi) -> Vec<(&'a String, &'a Value)> {
+        let Some(properties) = self
+            .body
+            .as_ref()
+            .and_then(|body| body.get("properties"))
+            .and_then(|p| p.as_object())
+        else {
+            return Vec::new();
+        };
+
+        properties
+            .iter()
+            .filter(|(name, _)| !self.parameters.iter().any(|p| &&p.name == name))
+            .map(|(name, schema)| (name, api.resolve(schema)))
+            .collect()
+    }
+
+    // command builds the clap command for the operation. Every parameter becomes a flag, as
+    // does every property of a JSON request body, with --raw-body to send a raw body instead.
+    // Each flag has an id for where it goes, as a parameter may share its name with one in
+    // another location, and the flag of such a parameter is prefixed with its location.
+    pub fn command(&self, api: &OpenApi) -> Command {
+        let mut command = Command::new(self.id.clone())
+            .no_binary_name(true)
+            .about(self.summary.clone().unwrap_or_default());
+
+        for parameter in self.parameters.iter() {
+            let mut arg = Arg::new(parameter.id())
+                .long(self.long(parameter))
+                .required(parameter.required)
+                .help(format!(
+                    "{} ({} parameter)",
+                    parameter.description.clone().unwrap_or_default(),
+                    parameter.location
+                ));
+            if let Some(default) = parameter.schema.as_ref().and_then(|s| s.get("default")) {
+                arg = arg.default_value(plain(default)).required(false);
+            }
+            command = command.arg(arg);
+        }
+
+        for (name, schema) in self.properties(api) {
+            command = command.arg(
+                Arg::new(format!("body.{name}"))
+                    .long(name.clone())
+                    .conflicts_with("raw-body")
+                    .help(format!(
+                        "{} (body property)",
+                        schema
+                            .get("description")
+                            .and_then(|d| d.as_str())
+                            .unwrap_or_default()
+                    )),
+            );
+        }
+
+        if self.body.is_some() || self.body_required {
+            command = command.arg(
+                Arg::new("raw-body")
+                    .long("raw-body")
+                    .action(ArgAction::Set)
+                    .help("The raw request body, a preceding @ denotes a file path"),
+            );
+        }
+
+        command
+    }
+
+    // long returns the flag of the parameter, which is its name unless another parameter in
+    // a different location has the same name
+    fn long(&self, parameter: &Parameter) -> String {
+        let shared = self
+            .parameters
+            .iter()
+            .filter(|other| other.name == parameter.name)
+            .count()
+            > 1;
+        match shared {
+            true => format!("{}-{}", parameter.location, parameter.name),
+            false => parameter.name.clone(),
+        }
+    }
+
+    // request_template converts the operation into a template that can be saved into the
+    // config and run with `kla run`. Parameters become template params, and the body is an
+    // example built from the schema. Optional query and header parameters without a default
+    // are left out, as they would otherwise always be sent.
+    pub fn request_template(&self, api: &OpenApi) -> RequestTemplate {
+        let mut template = RequestTemplate {
+            description: self.summary.clone(),
+            method: Some(self.method.clone()),
+            ..Default::default()
+        };
+
+        let mut path = self.path.clone();
+        for parameter in self.parameters.iter() {
+            let default = parameter
+                .schema
+                .as_ref()
+                .and_then(|s| s.get("default"))
+                .map(plain);
+            if !parameter.required && default.is_none() {
+                continue;
+            }
+
+            let name = identifier(&parameter.name);
+            let value = format!("{{{{ {name} }}}}");
+            match parameter.location.as_str() {
+                "path" => path = path.replace(&format!("{{{}}}", parameter.name), &value),
+                "query" => {
+                    template.query.insert(parameter.name.clone(), value);
+                }
+                "header" => {
+                    template.headers.insert(parameter.name.clone(), value);
+                }
+                _ => continue,
+            }
+
+            let kind = match parameter
+                .schema
+                .as_ref()
+                .and_then(|s| s.get("type"))
+                .and_then(|t| t.as_str())
+            {
+                Some("integer") => ParamType::Integer,
+                Some("number") => ParamType::Float,
+                Some("boolean") => ParamType::Boolean,
+                _ => ParamType::String,
+            };
+            template.params.push(Param {
+                name,
+                kind,
+                // a boolean is a flag that is false unless it is given
+                default: default.filter(|_| kind != ParamType::Boolean),
+                required: parameter.required,
+                help: parameter.description.clone(),
+            });
+        }
+        template.path = Some(path);
+
+        if let Some(body) = &self.body {
+            let example = example(api, body, &[]).unwrap_or_default();
+            template.body = serde_json::to_string_pretty(&example).ok();
+            template
+                .headers
+                .insert("Content-Type".to_owned(), "application/json".to_owned());
+        }
+
+        template
+    }
+
+    // template builds the request from the matched flags. Path parameters are substituted
+    // into the path, query and header parameters are routed to the query and headers, and
+    // the body properties are collected into a JSON object.
+    pub fn template(&self, api: &OpenApi, matches: &ArgMatches) -> Result<RequestTemplate, Error> {
+        let mut template = RequestTemplate {
+            description: self.summary.clone(),
+            method: Some(self.method.clone()),
+            ..Default::default()
+        };
+
+        let mut path = self.path.clone();
+        for parameter in self.parameters.iter() {
+            let Some(value) = matches.get_one::<String>(&parameter.id()) else {
+                continue;
+            };
+
+            match parameter.location.as_str() {
+                "path" => {
+                    let encoded = utf8_percent_encode(value, NON_ALPHANUMERIC).to_string();
+                    path = path.replace(&format!("{{{}}}", parameter.name), &encoded);
+                }
+                "query" => {
+                    template.query.insert(parameter.name.clone(), value.clone());
+                }
+                "header" => {
+                    template
+                        .headers
+                        .insert(parameter.name.clone(), value.clone());
+                }
+                "cookie" => {
+                    let cookie = format!("{}={value}", parameter.name);
+                    let cookies = match template.headers.remove("Cookie") {
+                        Some(cookies) => format!("{cookies}; {cookie}"),
+                        None => cookie,
+                    };
+                    template.headers.insert("Cookie".to_owned(), cookies);
+                }
+                _ => (),
+            }
+        }
+        template.path = Some(path);
+
+        let mut body = Map::new();
+        for (name, schema) in self.properties(api) {
+            if let Some(value) = matches.get_one::<String>(&format!("body.{name}")) {
+                body.insert(name.clone(), typed(name, value, schema)?);
+            }
+        }
+
+        let raw = matches
+            .try_get_one::<String>("raw-body")
+            .ok()
+            .flatten()
+            .cloned();
+        template.body = match raw {
+            // like the body argument of the root command, a preceding @ denotes a file path
+            Some(raw) => match raw.strip_prefix('@') {
+                Some(path) => {
+                    template.body_file = Some(path.to_owned());
+                    None
+                }
+                None => Some(raw),
+            },
+            None if !body.is_empty() || self.body_required => {
+                Some(serde_json::to_string(&Value::Object(body))?)
+            }
+            None => None,
+        };
+
+        let has_body = template.body.is_some() || template.body_file.is_some();
+        if has_body && self.body.is_some() {
+            template
+                .headers
+                .insert("Content-Type".to_owned(), "application/json".to_owned());
+        }
+
+        Ok(template)
+    }
+}
+
+impl Parameter {
+    // id is the id of the parameter's flag, which is unique across the locations
+    fn id(&self) -> String {
+        format!("{}.{}", self.location, self.name)
+    }
+}
+
+// example builds an example value for the schema, preferring the example or default that
+// the document gives. `seen` holds the references that led here, as schemas can refer to
+// themselves, and None is returned rather than going around forever.
+fn example(api: &OpenApi, schema: &Value, seen: &[&str]) -> Option<Value> {
+    let mut seen = seen.to_vec();
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        if seen.contains(&reference) {
+            return None;
+        }
+        seen.push(reference);
+    }
+    let schema = api.resolve(schema);
+
+    if let Some(example) = schema.get("example").or(schema.get("default")) {
+        return Some(example.clone());
+    }
+    if let Some(first) = schema.get("enum").and_then(|e| e.as_array()?.first()) {
+        return Some(first.clone());
+    }
+    for composed in ["allOf", "oneOf", "anyOf"] {
+        let Some(schemas) = schema.get(composed).and_then(|s| s.as_array()) else {
+            continue;
+        };
+        // every schema in an allOf applies, so their examples are merged together
+        let mut merged = Map::new();
+        for schema in schemas {
+            match example(api, schema, &seen) {
+                Some(Value::Object(object)) => merged.extend(object),
+                Some(value) if merged.is_empty() => return Some(value),
+                _ => (),
+            }
+            if composed != "allOf" {
+                break;
+            }
+        }
+        return Some(Value::Object(merged));
+    }
+
+    let value = match schema.get("type").and_then(|t| t.as_str()) {
+        Some("array") => Value::Array(
+            schema
+                .get("items")
+                .and_then(|items| example(api, items, &seen))
+                .into_iter()
+                .collect(),
+        ),
+        Some("integer") => Value::from(0),
+        Some("number") => Value::from(0.0),
+        Some("boolean") => Value::Bool(false),
+        Some("string") => Value::String("string".to_owned()),
+        _ => Value::Object(
+            schema
+                .get("properties")
+                .and_then(|p| p.as_object())
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, property)| Some((name.clone(), example(api, property, &seen)?)))
+                .collect(),
+        ),
+    };
+    Some(value)
+}
+
+// nullable rewrites the `nullable: true` of OpenAPI 3.0 into JSON Schema, which knows
+// nothing of it, by allowing null as another type. Schemas without a type, such as a $ref,
+// are wrapped so they match either themselves or null.
+fn nullable(schema: &mut Value) {
+    match schema {
+        Value::Array(values) => values.iter_mut().for_each(nullable),
+        Value::Object(object) => {
+            object.values_mut().for_each(nullable);
+            if object.get("nullable") != Some(&Value::Bool(true)) {
+                return;
+            }
+            object.remove("nullable");
+
+            if let Some(Value::Array(values)) = object.get_mut("enum") {
+                values.push(Value::Null);
+            }
+            match object.get_mut("type") {
+                Some(Value::String(kind)) => {
+                    let kind = Value::String(kind.clone());
+                    object.insert("type".to_owned(), Value::Array(vec![kind, "null".into()]));
+                }
+                Some(Value::Array(kinds)) => kinds.push("null".into()),
+                _ => {
+                    let inner = Value::Object(std::mem::take(object));
+                    object.insert(
+                        "anyOf".to_owned(),
+                        serde_json::json!([inner, { "type": "null" }]),
+                    );
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+// identifier turns the name of a parameter into one that can be used as a variable in tera,
+// so X-Request-Id becomes X_Request_Id
+fn identifier(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// typed converts the flag into the JSON type declared by the schema of the property
+fn typed(name: &str, value: &str, schema: &Value) -> Result<Value, Error> {
+    let invalid = |kind: &str| {
+        Error::InvalidArguments(format!("--{name} expects {kind}, but was given {value}"))
+    };
+
+    match schema.get("type").and_then(|t| t.as_str()) {
+        Some("integer") => value
+            .parse::<i64>()
+            .map(Value::from)
+            .map_err(|_| invalid("an integer")),
+        Some("number") => value
+            .parse::<f64>()
+            .map(Value::from)
+            .map_err(|_| invalid("a number")),
+        Some("boolean") => value
+            .parse::<bool>()
+            .map(Value::from)
+            .map_err(|_| invalid("true or false")),
+        Some("object") | Some("array") => {
+            serde_json::from_str(value).map_err(|_| invalid("a JSON value"))
+        }
+        _ => Ok(Value::String(value.to_owned())),
+    }
+}
+
+// path_values matches the path against the templated path from the document, such as
+// /users/{id}, returning the decoded value of each parameter when it matches
+fn path_values(template: &str, path: &str) -> Option<HashMap<String, String>> {
+    let parameter = Regex::new(r"\{([^}/]+)\}").ok()?;
+    let mut names = Vec::new();
+    let mut pattern = String::from("^");
+    let mut last = 0;
+    for captures in parameter.captures_iter(template) {
+        let whole = captures.get(0)?;
+        pattern.push_str(&regex::escape(&template[last..whole.start()]));
+        pattern.push_str("([^/]+)");
+        names.push(captures[1].to_owned());
+        last = whole.end();
+    }
+    pattern.push_str(&regex::escape(&template[last..]));
+    pattern.push('$');
+
+    let captures = Regex::new(&pattern).ok()?.captures(path)?;
+    Some(
+        names
+            .into_iter()
+            .zip(captures.iter().skip(1))
+            .filter_map(|(name, value)| {
+                let value = percent_decode_str(value?.as_str()).decode_utf8_lossy();
+                Some((name, value.into_owned()))
+            })
+            .collect(),
+    )
+}
+
+// coerce converts a parameter into the JSON type declared by its schema, leaving it as a
+// string when it can not be, so the schema reports the mismatch
+fn coerce(value: &str, schema: &Value) -> Value {
+    let coerced = match schema.get("type").and_then(|t| t.as_str()) {
+        Some("integer") => value.parse::<i64>().ok().map(Value::from),
+        Some("number") => value.parse::<f64>().ok().map(Value::from),
+        Some("boolean") => value.parse::<bool>().ok().map(Value::from),
+        _ => None,
+    };
+    coerced.unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+// plain returns the value as it would be typed on the command line, without quotes
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn api(version: &str) -> OpenApi {
+        OpenApi {
+            path: "api.yaml".to_owned(),
+            document: json!({
+                "openapi": version,
+                "components": {
+                    "schemas": {
+                        "User": {
+                            "type": "object",
+                            "properties": {
+                                "name": { "type": "string", "nullable": true },
+                                "role": { "type": "string", "enum": ["admin"], "nullable": true },
+                                "manager": { "$ref": "#/components/schemas/User", "nullable": true },
+                                "nullable": { "type": "boolean" }
+                            }
+                        }
+                    }
+                }
+            }),
+            compiled: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn nullable_allows_null() {
+        let api = api("3.0.3");
+        let schema = json!({ "$ref": "#/components/schemas/User" });
+
+        let valid = json!({ "name": null, "role": null, "manager": null, "nullable": true });
+        assert_eq!(
+            api.check("body", &schema, &valid).unwrap(),
+            Vec::<String>::new()
+        );
+
+        let invalid =
+            json!({ "name": 1, "role": "user", "manager": { "name": 2 }, "nullable": null });
+        assert_eq!(api.check("body", &schema, &invalid).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn nullable_is_ignored_after_3_0() {
+        let api = api("3.1.0");
+        let schema = json!({ "$ref": "#/components/schemas/User" });
+
+        assert_eq!(
+            api.check("body", &schema, &json!({ "name": null }))
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn schemas_are_compiled_once() {
+        let api = api("3.0.3");
+        let schema = json!({ "type": "integer" });
+
+        assert!(api.check("body", &schema, &json!(1)).unwrap().is_empty());
+        assert_eq!(api.check("body", &schema, &json!("1")).unwrap().len(), 1);
+        api.check("body", &json!({ "type": "string" }), &json!("1"))
+            .unwrap();
+        assert_eq!(api.compiled.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn flags_do_not_collide() {
+        let api = api("3.1.0");
+        let parameter = |location: &str| Parameter {
+            name: "id".to_owned(),
+            location: location.to_owned(),
+            required: false,
+            description: None,
+            schema: None,
+        };
+        let operation = Operation {
+            id: "createComment".to_owned(),
+            method: "POST".to_owned(),
+            path: "/issues/{id}/comments".to_owned(),
+            summary: None,
+            parameters: vec![parameter("path"), parameter("query")],
+            body: Some(json!({
+                "type": "object",
+                "properties": { "body": { "type": "string" } }
+            })),
+            body_required: true,
+            responses: json!({}),
+        };
+
+        operation.command(&api).debug_assert();
+        let matches = operation
+            .command(&api)
+            .try_get_matches_from(["--path-id", "3", "--query-id", "4", "--body", "hi"])
+            .unwrap();
+        let template = operation.template(&api, &matches).unwrap();
+        assert_eq!(template.path.as_deref(), Some("/issues/3/comments"));
+        assert_eq!(template.query["id"], "4");
+        assert_eq!(template.body.as_deref(), Some(r#"{"body":"hi"}"#));
+
+        let matches = operation
+            .command(&api)
+            .try_get_matches_from(["--raw-body", "hi"])
+            .unwrap();
+        let template = operation.template(&api, &matches).unwrap();
+        assert_eq!(template.body.as_deref(), Some("hi"));
+    }
+}
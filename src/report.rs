@@ -0,0 +1,181 @@
+use crate::Error;
+use std::{io::Write, time::Duration};
+
+// TestResult is the outcome of running a single test file with `kla test`
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: String,
+    pub duration: Duration,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    // an assertion did not hold
+    Failed(String),
+    // the test could not be run, eg the server could not be reached
+    Errored(String),
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        self.outcome == Outcome::Passed
+    }
+
+    fn message(&self) -> Option<&str> {
+        match &self.outcome {
+            Outcome::Passed => None,
+            Outcome::Failed(msg) | Outcome::Errored(msg) => Some(msg),
+        }
+    }
+}
+
+// TestReport holds the results of a `kla test` run, and writes them out in each of the
+// supported formats.
+#[derive(Debug, Default, Clone)]
+pub struct TestReport {
+    pub results: Vec<TestResult>,
+}
+
+impl TestReport {
+    pub fn new(results: Vec<TestResult>) -> TestReport {
+        TestReport { results }
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.iter().filter(|r| !r.passed()).count()
+    }
+
+    // write_summary writes a line per test followed by the totals, this is what is shown
+    // on stdout
+    pub fn write_summary(&self, output: &mut dyn Write) -> Result<(), Error> {
+        summary(output, &self.results)
+    }
+
+    // write_junit writes the results as JUnit XML, which most CI systems can display
+    pub fn write_junit(&self, output: &mut dyn Write) -> Result<(), Error> {
+        junit(output, &self.results)
+    }
+
+    // write_tap writes the results in the Test Anything Protocol, with each failure in a
+    // YAML block
+    pub fn write_tap(&self, output: &mut dyn Write) -> Result<(), Error> {
+        tap(output, &self.results)
+    }
+}
+
+fn summary(output: &mut dyn Write, results: &[TestResult]) -> Result<(), Error> {
+    for result in results {
+        let status = match result.outcome {
+            Outcome::Passed => "ok",
+            Outcome::Failed(_) => "FAIL",
+            Outcome::Errored(_) => "ERROR",
+        };
+        writeln!(
+            output,
+            "{status:<6}{} ({}ms)",
+            result.name,
+            result.duration.as_millis()
+        )?;
+        if let Some(msg) = result.message() {
+            for line in msg.lines() {
+                writeln!(output, "      {line}")?;
+            }
+        }
+    }
+
+    let passed = results.iter().filter(|r| r.passed()).count();
+    writeln!(
+        output,
+        "\n{} tests, {passed} passed, {} failed",
+        results.len(),
+        results.len() - passed
+    )?;
+    Ok(())
+}
+
+fn junit(output: &mut dyn Write, results: &[TestResult]) -> Result<(), Error> {
+    let failures = results
+        .iter()
+        .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+        .count();
+    let errors = results
+        .iter()
+        .filter(|r| matches!(r.outcome, Outcome::Errored(_)))
+        .count();
+    let time: Duration = results.iter().map(|r| r.duration).sum();
+
+    writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        output,
+        r#"<testsuites tests="{}" failures="{failures}" errors="{errors}" time="{:.3}">"#,
+        results.len(),
+        time.as_secs_f64()
+    )?;
+    writeln!(
+        output,
+        r#"  <testsuite name="kla" tests="{}" failures="{failures}" errors="{errors}" time="{:.3}">"#,
+        results.len(),
+        time.as_secs_f64()
+    )?;
+
+    for result in results {
+        let open = format!(
+            r#"    <testcase name="{}" classname="kla" time="{:.3}""#,
+            xml_escape(&result.name),
+            result.duration.as_secs_f64()
+        );
+        let (element, msg) = match &result.outcome {
+            Outcome::Passed => {
+                writeln!(output, "{open}/>")?;
+                continue;
+            }
+            Outcome::Failed(msg) => ("failure", msg),
+            Outcome::Errored(msg) => ("error", msg),
+        };
+
+        writeln!(output, "{open}>")?;
+        writeln!(
+            output,
+            r#"      <{element} message="{}">{}</{element}>"#,
+            xml_escape(msg.lines().next().unwrap_or_default()),
+            xml_escape(msg)
+        )?;
+        writeln!(output, "    </testcase>")?;
+    }
+
+    writeln!(output, "  </testsuite>")?;
+    writeln!(output, "</testsuites>")?;
+    Ok(())
+}
+
+fn tap(output: &mut dyn Write, results: &[TestResult]) -> Result<(), Error> {
+    writeln!(output, "TAP version 13")?;
+    writeln!(output, "1..{}", results.len())?;
+
+    for (i, result) in results.iter().enumerate() {
+        let status = if result.passed() { "ok" } else { "not ok" };
+        writeln!(output, "{status} {} - {}", i + 1, result.name)?;
+
+        if let Some(msg) = result.message() {
+            writeln!(output, "  ---")?;
+            writeln!(output, "  message: |")?;
+            for line in msg.lines() {
+                writeln!(output, "    {line}")?;
+            }
+            writeln!(output, "  duration_ms: {}", result.duration.as_millis())?;
+            writeln!(output, "  ...")?;
+        }
+    }
+    Ok(())
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
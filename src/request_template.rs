@@ -0,0 +1,262 @@
+use crate::{render, secret, Environment, Error};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tera::Context;
+
+// RequestTemplate is a named request saved under an environment in the config. Each of the
+// fields lines up with an argument on the command line, so a template is nothing more than
+// a saved kla invocation.
+//
+//   [environment.github.template.user]
+//   description = "fetch a single user"
+//   method = "GET"
+//   path = "/users/d1ngd0"
+//   headers = { Accept = "application/vnd.github+json" }
+//   query = { per_page = "10" }
+//   template = "{{ login }}"
+//   schema = "schemas/user.json"
+//
+// Templates may also declare parameters, which become flags on the command line. The values
+// are available while rendering the path, headers, query, form and body of the template.
+//
+//   [[environment.github.template.user.params]]
+//   name = "username"
+//   type = "string"
+//   required = true
+//   help = "The user to fetch"
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct RequestTemplate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub query: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub form: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub multipart: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub basic_auth: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub params: Vec<Param>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Integer,
+    Float,
+    Boolean,
+}
+
+// Param is a single parameter accepted by a template. It is exposed as `--<name>` when the
+// template is run.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Param {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: ParamType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub help: Option<String>,
+}
+
+impl Param {
+    fn arg(&self) -> Arg {
+        let mut arg = Arg::new(self.name.clone())
+            .long(self.name.clone())
+            .required(self.required && self.default.is_none());
+
+        arg = match self.kind {
+            ParamType::String => arg.value_parser(value_parser!(String)),
+            ParamType::Integer => arg.value_parser(value_parser!(i64)),
+            ParamType::Float => arg.value_parser(value_parser!(f64)),
+            ParamType::Boolean => arg.action(ArgAction::SetTrue),
+        };
+
+        if let Some(help) = &self.help {
+            arg = arg.help(help.clone());
+        }
+
+        if let Some(default) = &self.default {
+            arg = arg.default_value(default.clone());
+        }
+
+        arg
+    }
+
+    fn insert(&self, matches: &ArgMatches, context: &mut Context) {
+        let name = self.name.as_str();
+        match self.kind {
+            ParamType::String => {
+                if let Some(v) = matches.get_one::<String>(name) {
+                    context.insert(name, v);
+                }
+            }
+            ParamType::Integer => {
+                if let Some(v) = matches.get_one::<i64>(name) {
+                    context.insert(name, v);
+                }
+            }
+            ParamType::Float => {
+                if let Some(v) = matches.get_one::<f64>(name) {
+                    context.insert(name, v);
+                }
+            }
+            ParamType::Boolean => context.insert(name, &matches.get_flag(name)),
+        }
+    }
+}
+
+impl RequestTemplate {
+    // command builds the clap command for the template, exposing each of the parameters as
+    // a flag so they are validated and show up in `--help`.
+    pub fn command(&self, name: &str) -> Command {
+        let mut command = Command::new(name.to_owned()).no_binary_name(true);
+
+        if let Some(description) = &self.description {
+            command = command.about(description.clone());
+        }
+
+        command.args(self.params.iter().map(Param::arg))
+    }
+
+    // context adds the parameters that were matched by the command returned from `command`
+    // to the tera context.
+    pub fn context(&self, matches: &ArgMatches, mut context: Context) -> Context {
+        for param in self.params.iter() {
+            param.insert(matches, &mut context);
+        }
+        context
+    }
+
+    // render runs every part of the request through tera, returning a template with the
+    // values filled in.
+    pub fn render(&self, context: &Context) -> Result<RequestTemplate, Error> {
+        self.render_with(|value, _| render(value, context))
+    }
+
+    // render_resolved renders the template like `render`, then resolves the secret
+    // references in its values. It is used for the templates saved in the config, `key` being
+    // the path to the template, eg environment.github.template.user.
+    pub fn render_resolved(&self, context: &Context, key: &str) -> Result<RequestTemplate, Error> {
+        self.render_with(|value, field| secret::render(value, context, &format!("{key}.{field}")))
+    }
+
+    fn render_with<F>(&self, render: F) -> Result<RequestTemplate, Error>
+    where
+        F: Fn(&str, &str) -> Result<String, Error>,
+    {
+        let render_opt = |value: &Option<String>, field: &str| {
+            value.as_ref().map(|value| render(value, field)).transpose()
+        };
+        let render_map = |map: &BTreeMap<String, String>, field: &str| {
+            map.iter()
+                .map(|(k, v)| Ok((k.clone(), render(v, &format!("{field}.{k}"))?)))
+                .collect::<Result<BTreeMap<String, String>, Error>>()
+        };
+
+        Ok(RequestTemplate {
+            description: self.description.clone(),
+            method: render_opt(&self.method, "method")?,
+            path: render_opt(&self.path, "path")?,
+            headers: render_map(&self.headers, "headers")?,
+            query: render_map(&self.query, "query")?,
+            form: render_map(&self.form, "form")?,
+            multipart: render_map(&self.multipart, "multipart")?,
+            body: render_opt(&self.body, "body")?,
+            body_file: render_opt(&self.body_file, "body_file")?,
+            basic_auth: render_opt(&self.basic_auth, "basic_auth")?,
+            template: self.template.clone(),
+            failure_template: self.failure_template.clone(),
+            schema: self.schema.clone(),
+            params: self.params.clone(),
+        })
+    }
+
+    // inherit fills in the headers and query parameters from the environment that the
+    // template does not set itself. The environment's values are rendered with the context
+    // and have their secrets resolved, so this comes after the template has been rendered.
+    pub fn inherit(mut self, env: &Environment, context: &Context) -> Result<Self, Error> {
+        for (name, value) in env.headers.iter() {
+            if !self.headers.keys().any(|k| k.eq_ignore_ascii_case(name)) {
+                let value = secret::render(value, context, &env.key(&format!("headers.{name}")))?;
+                self.headers.insert(name.clone(), value);
+            }
+        }
+
+        for (name, value) in env.query.iter() {
+            if !self.query.contains_key(name) {
+                let value = secret::render(value, context, &env.key(&format!("query.{name}")))?;
+                self.query.insert(name.clone(), value);
+            }
+        }
+
+        Ok(self)
+    }
+
+    // args returns the method and path in the same shape as the positional arguments that
+    // are passed on the command line, so they can be handed to `KlaClient::args`. The body is
+    // left out, as the command line reads a leading @ or - in it as a file or stdin.
+    pub fn args(&self) -> Vec<String> {
+        vec![
+            self.method.clone().unwrap_or_else(|| "GET".to_owned()),
+            self.path.clone().unwrap_or_else(|| "/".to_owned()),
+        ]
+    }
+
+    // headers returns the headers formatted like the `--header` argument
+    pub fn headers(&self) -> Option<Vec<String>> {
+        Self::pairs(&self.headers, ": ")
+    }
+
+    // query returns the query parameters formatted like the `--query` argument
+    pub fn query(&self) -> Option<Vec<String>> {
+        Self::pairs(&self.query, "=")
+    }
+
+    // form returns the form values formatted like the `--form` argument
+    pub fn form(&self) -> Option<Vec<String>> {
+        Self::pairs(&self.form, "=")
+    }
+
+    // multipart returns the fields of the multipart form formatted like curl's `-F`
+    pub fn multipart(&self) -> Option<Vec<String>> {
+        Self::pairs(&self.multipart, "=")
+    }
+
+    // pairs joins each key and value with the separator, returning None when the map is
+    // empty so nothing is applied to the request.
+    fn pairs(map: &BTreeMap<String, String>, separator: &str) -> Option<Vec<String>> {
+        if map.is_empty() {
+            return None;
+        }
+
+        Some(
+            map.iter()
+                .map(|(name, value)| format!("{name}{separator}{value}"))
+                .collect(),
+        )
+    }
+}
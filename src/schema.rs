@@ -0,0 +1,77 @@
+use crate::Error;
+use jsonschema::{Draft, JSONSchema, ValidationError};
+use serde_json::Value;
+use std::fs;
+use tera::Context;
+
+// Schema is a JSON Schema the response body is validated against. Schemas are treated as
+// draft 2020-12 unless they declare another draft with `$schema`.
+pub struct Schema {
+    path: String,
+    schema: JSONSchema,
+}
+
+impl Schema {
+    pub fn open(path: &str) -> Result<Schema, Error> {
+        let value: Value = serde_json::from_str(&fs::read_to_string(path)?)
+            .map_err(|err| Error::InvalidArguments(format!("{path}: {err}")))?;
+
+        let mut options = JSONSchema::options();
+        if value.get("$schema").is_none() {
+            options.with_draft(Draft::Draft202012);
+        }
+        let schema = options
+            .compile(&value)
+            .map_err(|err| Error::InvalidArguments(format!("{path}: {err}")))?;
+
+        Ok(Schema {
+            path: path.to_owned(),
+            schema,
+        })
+    }
+
+    // validate checks the body of the response, failing with every violation listed along
+    // with where it is in the body and the keyword of the schema it broke.
+    pub fn validate(&self, response: &Context) -> Result<(), Error> {
+        let content = response
+            .get("resp_body")
+            .and_then(|body| body.as_str())
+            .unwrap_or_default();
+        let body: Value = serde_json::from_str(content).map_err(|_| {
+            Error::AssertionFailed(format!(
+                "the response body is not JSON, so it can not be validated against {}",
+                self.path
+            ))
+        })?;
+
+        let Err(errors) = self.schema.validate(&body) else {
+            return Ok(());
+        };
+
+        let mut failures = vec![format!(
+            "the response body does not match the schema {}",
+            self.path
+        )];
+        for error in errors {
+            failures.push(format!("  {}", violation("", &error)));
+        }
+
+        Err(Error::AssertionFailed(failures.join("\n")))
+    }
+}
+
+// violation describes the error along with where it is in the subject and the keyword of
+// the schema it broke
+pub(crate) fn violation(subject: &str, error: &ValidationError) -> String {
+    let pointer = match error.instance_path.to_string() {
+        pointer if pointer.is_empty() && subject.is_empty() => "/".to_owned(),
+        pointer => pointer,
+    };
+    let keyword = error
+        .schema_path
+        .clone()
+        .into_vec()
+        .pop()
+        .unwrap_or_default();
+    format!("{subject}{pointer}: {error} ({keyword})")
+}
@@ -0,0 +1,179 @@
+use crate::Error;
+use rand::Rng;
+use regex::{Captures, Regex};
+use serde_json::Value as JsonValue;
+use std::{env, fs, process::Command};
+use tera::Context;
+
+const REFERENCE: &str = r"\$\{(env|file|cmd):([^}]*)\}";
+
+// resolve replaces the secret references in the value. `key` is the path to the value
+// within the config, and is used to point at the offending key when a secret cannot be
+// resolved. References take one of the following forms, and can be embedded within a larger
+// string (eg "Bearer ${env:GITHUB_TOKEN}").
+//
+//   ${env:NAME}     the value of the environment variable NAME
+//   ${file:PATH}    the contents of the file at PATH, a leading ~ is the home directory
+//   ${cmd:COMMAND}  the output of running COMMAND with sh
+//
+// Secrets are resolved when the value is used rather than when the config is loaded, so a
+// command is only run for the requests that need it.
+pub fn resolve(value: &str, key: &str) -> Result<String, Error> {
+    if !value.contains("${") {
+        return Ok(value.to_owned());
+    }
+
+    substitute(value, &Regex::new(REFERENCE)?, key, |caps| {
+        lookup(&caps[1], caps[2].trim())
+    })
+}
+
+// render runs the value through tera and then resolves the secret references it holds. The
+// references are swapped for placeholders while rendering, so a secret is never parsed as a
+// template, and references can not be slipped in through the values being rendered, such
+// as a value captured from a response.
+pub fn render(value: &str, context: &Context, key: &str) -> Result<String, Error> {
+    if !value.contains("${") {
+        return crate::render(value, context);
+    }
+
+    let nonce = hex::encode(rand::thread_rng().gen::<[u8; 8]>());
+    let mut references = Vec::new();
+    let masked = Regex::new(REFERENCE)?.replace_all(value, |caps: &Captures| {
+        references.push((caps[1].to_owned(), caps[2].trim().to_owned()));
+        format!("kla-secret-{nonce}-{}", references.len() - 1)
+    });
+    let rendered = crate::render(&masked, context)?;
+
+    let placeholder = Regex::new(&format!("kla-secret-{nonce}-([0-9]+)"))?;
+    substitute(&rendered, &placeholder, key, |caps| {
+        let (kind, reference) = caps[1]
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| references.get(i))
+            .ok_or_else(|| "the secret reference was lost while rendering".to_owned())?;
+        lookup(kind, reference)
+    })
+}
+
+// resolve_json resolves the secret references in every string within the value
+pub fn resolve_json(value: &JsonValue, key: &str) -> Result<JsonValue, Error> {
+    Ok(match value {
+        JsonValue::String(s) => JsonValue::String(resolve(s, key)?),
+        JsonValue::Array(array) => JsonValue::Array(
+            array
+                .iter()
+                .enumerate()
+                .map(|(i, v)| resolve_json(v, &format!("{key}[{i}]")))
+                .collect::<Result<_, Error>>()?,
+        ),
+        JsonValue::Object(object) => JsonValue::Object(
+            object
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), resolve_json(v, &format!("{key}.{k}"))?)))
+                .collect::<Result<_, Error>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+// substitute replaces every match of the regex with the value returned by lookup, failing
+// with the first error
+fn substitute<F>(value: &str, re: &Regex, key: &str, lookup: F) -> Result<String, Error>
+where
+    F: Fn(&Captures) -> Result<String, String>,
+{
+    let mut err = None;
+    let resolved = re.replace_all(value, |caps: &Captures| match lookup(caps) {
+        Ok(v) => v,
+        Err(msg) => {
+            err.get_or_insert(Error::ConfigError(format!("{key}: {msg}")));
+            String::new()
+        }
+    });
+
+    match err {
+        Some(err) => Err(err),
+        None => Ok(resolved.into_owned()),
+    }
+}
+
+fn lookup(kind: &str, reference: &str) -> Result<String, String> {
+    match kind {
+        "env" => {
+            env::var(reference).map_err(|_| format!("environment variable {reference} is not set"))
+        }
+        "file" => {
+            let path = match reference.strip_prefix("~/") {
+                Some(rest) => format!("{}/{rest}", env::var("HOME").unwrap_or_default()),
+                None => reference.to_owned(),
+            };
+
+            fs::read_to_string(&path)
+                .map(|v| v.trim_end_matches(['\r', '\n']).to_owned())
+                .map_err(|err| format!("could not read {path}: {err}"))
+        }
+        "cmd" => {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(reference)
+                .output()
+                .map_err(|err| format!("could not run `{reference}`: {err}"))?;
+
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(match stderr.trim() {
+                    "" => format!("`{reference}` exited with {}", output.status),
+                    stderr => format!("`{reference}` exited with {}: {stderr}", output.status),
+                });
+            }
+
+            String::from_utf8(output.stdout)
+                .map(|v| v.trim_end_matches(['\r', '\n']).to_owned())
+                .map_err(|_| format!("`{reference}` did not output valid UTF-8"))
+        }
+        _ => Err(format!("unknown secret reference {kind}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_does_not_parse_secrets_as_templates() {
+        let path = env::temp_dir().join("kla-secret-test");
+        fs::write(&path, "{{ oops %}\n").unwrap();
+        let mut context = Context::new();
+        context.insert("name", "kla");
+
+        let rendered = render(
+            &format!("{{{{ name }}}} ${{file:{}}}", path.display()),
+            &context,
+            "environment.test.headers.X",
+        )
+        .unwrap();
+        assert_eq!(rendered, "kla {{ oops %}");
+    }
+
+    #[test]
+    fn render_does_not_resolve_references_from_the_context() {
+        let mut context = Context::new();
+        context.insert("captured", "${cmd:echo injected}");
+
+        let rendered = render("{{ captured }}", &context, "environment.test.body").unwrap();
+        assert_eq!(rendered, "${cmd:echo injected}");
+    }
+
+    #[test]
+    fn resolve_names_the_key() {
+        let err = resolve("${env:KLA_TEST_NOT_SET}", "environment.test.bearer_token").unwrap_err();
+        let Error::ConfigError(msg) = err else {
+            unreachable!()
+        };
+        assert_eq!(
+            msg,
+            "environment.test.bearer_token: environment variable KLA_TEST_NOT_SET is not set"
+        );
+    }
+}
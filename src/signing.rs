@@ -0,0 +1,288 @@
+use crate::{secret, Error};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Request,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tera::Context;
+
+// Signing is a recipe for signing every request to an environment with an HMAC, for the
+// many APIs that each want their own variation of it. The string to sign and the headers
+// are tera templates, rendered with the variables of the request along with these.
+//
+//   method       the method of the request, such as POST
+//   url          the full url of the request
+//   path         the path of the url
+//   query        the query string of the url, without the ?
+//   host         the host of the url, with the port when there is one
+//   headers      the headers of the request, by their lowercase name
+//   body         the body of the request
+//   body_sha256  the hex encoded sha256 hash of the body
+//   timestamp    the seconds since the unix epoch, timestamp_ms for milliseconds
+//   nonce        a random hex string, unique to the request
+//   signature    the signature, only available to the headers
+//
+//   [environment.partner.signing]
+//   algorithm = "HMAC-SHA256"
+//   secret = "${env:PARTNER_SECRET}"
+//   string_to_sign = "{{ method }}\n{{ path }}\n{{ timestamp }}\n{{ body_sha256 }}"
+//   encoding = "base64"
+//   headers = { X-Timestamp = "{{ timestamp }}", X-Signature = "{{ signature }}" }
+#[derive(Debug, Clone, Deserialize)]
+pub struct Signing {
+    #[serde(default = "default_algorithm")]
+    pub algorithm: String,
+    pub secret: String,
+    pub string_to_sign: String,
+    // hex or base64, defaults to hex
+    #[serde(default)]
+    pub encoding: Option<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+fn default_algorithm() -> String {
+    "HMAC-SHA256".to_owned()
+}
+
+impl Signing {
+    // sign adds the headers to the request, rendered with the signature of the string to
+    // sign. The request must be final, any change made to it afterwards breaks the signature.
+    // key names the recipe in the config, eg environment.partner.signing, for the errors of
+    // its secrets.
+    pub fn sign(
+        &self,
+        request: &mut Request,
+        context: &Context,
+        time: SystemTime,
+        key: &str,
+    ) -> Result<(), Error> {
+        let mut context = context.clone();
+        let url = request.url();
+        let body = match request.body().map(|body| body.as_bytes()) {
+            Some(Some(body)) => body,
+            None => &[],
+            Some(None) => {
+                return Err(Error::InvalidArguments(
+                    "the request body is a stream, which can not be signed".to_owned(),
+                ))
+            }
+        };
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let headers: HashMap<String, String> = request
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_owned(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect();
+
+        context.insert("method", request.method().as_str());
+        context.insert("url", url.as_str());
+        context.insert("path", url.path());
+        context.insert("query", url.query().unwrap_or_default());
+        context.insert(
+            "host",
+            &match url.port() {
+                Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+                None => url.host_str().unwrap_or_default().to_owned(),
+            },
+        );
+        context.insert("headers", &headers);
+        context.insert("body", &String::from_utf8_lossy(body));
+        context.insert("body_sha256", &hex::encode(Sha256::digest(body)));
+        context.insert("timestamp", &(millis / 1000).to_string());
+        context.insert("timestamp_ms", &millis.to_string());
+        context.insert("nonce", &hex::encode(rand::thread_rng().gen::<[u8; 16]>()));
+
+        let string_to_sign = secret::render(
+            &self.string_to_sign,
+            &context,
+            &format!("{key}.string_to_sign"),
+        )?;
+        let signature = self.signature(string_to_sign.as_bytes(), key)?;
+        context.insert("signature", &signature);
+
+        for (name, value) in self.headers.iter() {
+            request.headers_mut().insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(&secret::render(
+                    value,
+                    &context,
+                    &format!("{key}.headers.{name}"),
+                )?)?,
+            );
+        }
+        Ok(())
+    }
+
+    fn signature(&self, data: &[u8], key: &str) -> Result<String, Error> {
+        let invalid = |err: hmac::digest::InvalidLength| Error::ConfigError(err.to_string());
+        let secret = secret::resolve(&self.secret, &format!("{key}.secret"))?;
+        let secret = secret.as_bytes();
+
+        let signature = match self.algorithm.to_uppercase().as_str() {
+            "HMAC-SHA256" => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(invalid)?;
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            "HMAC-SHA512" => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).map_err(invalid)?;
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            algorithm => {
+                return Err(Error::ConfigError(format!(
+                    "the signing algorithm {algorithm} is not supported, use HMAC-SHA256 or HMAC-SHA512"
+                )))
+            }
+        };
+
+        match self.encoding.as_deref() {
+            None | Some("hex") => Ok(hex::encode(signature)),
+            Some("base64") => Ok(STANDARD.encode(signature)),
+            Some(encoding) => Err(Error::ConfigError(format!(
+                "the signature encoding {encoding} is not supported, use hex or base64"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{Body, Client};
+    use std::time::Duration;
+
+    // the HMAC examples from RFC 4231, test case 2
+    const SECRET: &str = "Jefe";
+    const DATA: &[u8] = b"what do ya want for nothing?";
+    const KEY: &str = "environment.partner.signing";
+
+    fn signing(algorithm: &str, encoding: Option<&str>) -> Signing {
+        Signing {
+            algorithm: algorithm.to_owned(),
+            secret: SECRET.to_owned(),
+            string_to_sign: String::new(),
+            encoding: encoding.map(|e| e.to_owned()),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn hmac_sha256() {
+        assert_eq!(
+            signing("HMAC-SHA256", None).signature(DATA, KEY).unwrap(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            signing("hmac-sha256", Some("base64"))
+                .signature(DATA, KEY)
+                .unwrap(),
+            "W9zBRr9gdU5qBCQmCJV1x1oAPwidJzmDnexYuWTsOEM="
+        );
+    }
+
+    #[test]
+    fn hmac_sha512() {
+        assert_eq!(
+            signing("HMAC-SHA512", Some("hex")).signature(DATA, KEY).unwrap(),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+        assert_eq!(
+            signing("HMAC-SHA512", Some("base64")).signature(DATA, KEY).unwrap(),
+            "Fkt6e/z4GeLjlfvnO1bgo4e9ZCIugx/WECcM1+olBVSXWL91wFqZSm0DT2X48Ob9yuqxo01Ka0tjbgcKOLznNw=="
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(signing("HMAC-MD5", None).signature(DATA, KEY).is_err());
+        assert!(signing("HMAC-SHA256", Some("base32"))
+            .signature(DATA, KEY)
+            .is_err());
+    }
+
+    #[test]
+    fn sign_renders_the_string_to_sign() {
+        let mut signing = signing("HMAC-SHA256", None);
+        signing.string_to_sign =
+            "{{ method }}\n{{ path }}\n{{ query }}\n{{ timestamp }}\n{{ body_sha256 }}".to_owned();
+        signing.headers = BTreeMap::from([
+            ("X-Signature".to_owned(), "{{ signature }}".to_owned()),
+            ("X-Timestamp".to_owned(), "{{ timestamp }}".to_owned()),
+            ("X-Client".to_owned(), "{{ client }}@{{ host }}".to_owned()),
+        ]);
+
+        let mut request = Client::new()
+            .post("http://api.example.com:8080/orders?id=7")
+            .body(r#"{"a":1}"#)
+            .build()
+            .unwrap();
+        let mut context = Context::new();
+        context.insert("client", "kla");
+        signing
+            .sign(
+                &mut request,
+                &context,
+                UNIX_EPOCH + Duration::from_secs(1440938160),
+                KEY,
+            )
+            .unwrap();
+
+        let headers = request.headers();
+        assert_eq!(
+            headers["x-signature"],
+            "f37a7e4d2c3d9c0e155d9d3554762b601b5859a780f5768b6e94a7ec1ac265f9"
+        );
+        assert_eq!(headers["x-timestamp"], "1440938160");
+        assert_eq!(headers["x-client"], "kla@api.example.com:8080");
+    }
+
+    #[test]
+    fn sign_rejects_stream_bodies() {
+        let stream = futures::stream::iter(vec![Ok::<_, std::io::Error>("body")]);
+        let mut request = Client::new()
+            .post("http://api.example.com/orders")
+            .body(Body::wrap_stream(stream))
+            .build()
+            .unwrap();
+
+        let result = signing("HMAC-SHA256", None).sign(
+            &mut request,
+            &Context::new(),
+            SystemTime::now(),
+            KEY,
+        );
+        assert!(result.is_err());
+        assert!(!request.headers().contains_key("x-signature"));
+    }
+
+    #[test]
+    fn secret_errors_name_the_environment() {
+        let mut signing = signing("HMAC-SHA256", None);
+        signing.secret = "${env:KLA_SIGNING_TEST_UNSET}".to_owned();
+
+        let Err(Error::ConfigError(msg)) = signing.signature(DATA, KEY) else {
+            unreachable!()
+        };
+        assert_eq!(
+            msg,
+            "environment.partner.signing.secret: environment variable KLA_SIGNING_TEST_UNSET is not set"
+        );
+    }
+}
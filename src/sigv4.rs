@@ -0,0 +1,529 @@
+use crate::Error;
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION, USER_AGENT},
+    Request,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+// sent in place of the hash of the body when it is not part of the signature
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+// everything but the unreserved characters of RFC 3986 is encoded
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// AwsSigV4 signs requests with AWS Signature Version 4 for a service in a region, given as
+// service:region on the command line or in the environment.
+//
+//   [environment.search]
+//   url = "https://search-logs.us-east-1.es.amazonaws.com"
+//   aws_sigv4 = "es:us-east-1"
+#[derive(Debug, Clone)]
+pub struct AwsSigV4 {
+    pub service: String,
+    pub region: String,
+}
+
+// AwsCredentials are read from AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and
+// AWS_SESSION_TOKEN, or the AWS_PROFILE (default) profile of ~/.aws/credentials.
+#[derive(Debug, Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsSigV4 {
+    pub fn parse(value: &str) -> Result<AwsSigV4, Error> {
+        match value.split_once(':') {
+            Some((service, region)) if !service.is_empty() && !region.is_empty() => Ok(AwsSigV4 {
+                service: service.to_owned(),
+                region: region.to_owned(),
+            }),
+            _ => Err(Error::InvalidArguments(format!(
+                "{value} is not a valid service:region, such as execute-api:us-east-1"
+            ))),
+        }
+    }
+
+    // sign adds the x-amz-date and Authorization headers to the request, along with
+    // x-amz-security-token for temporary credentials. The request must be final, any change
+    // made to it afterwards breaks the signature.
+    pub fn sign(
+        &self,
+        request: &mut Request,
+        credentials: &AwsCredentials,
+        time: SystemTime,
+    ) -> Result<(), Error> {
+        let (date, timestamp) = timestamp(time);
+        let payload = match request.body().map(|body| body.as_bytes()) {
+            Some(Some(body)) => hex::encode(Sha256::digest(body)),
+            None => hex::encode(Sha256::digest(b"")),
+            // a stream can not be hashed without reading it, which s3 allows to go unsigned
+            Some(None) if self.service == "s3" => UNSIGNED_PAYLOAD.to_owned(),
+            Some(None) => {
+                return Err(Error::InvalidArguments(format!(
+                    "the request body is a stream, which can not be signed for {}",
+                    self.service
+                )))
+            }
+        };
+
+        let headers = request.headers_mut();
+        headers.remove(AUTHORIZATION);
+        headers.insert("x-amz-date", HeaderValue::from_str(&timestamp)?);
+        if let Some(token) = &credentials.session_token {
+            headers.insert("x-amz-security-token", HeaderValue::from_str(token)?);
+        }
+        // s3 is the one service that wants to be told the hash of the payload, or that it is
+        // unsigned
+        if self.service == "s3" {
+            headers.insert("x-amz-content-sha256", HeaderValue::from_str(&payload)?);
+        }
+
+        let (canonical_request, signed_headers) = self.canonical_request(request, &payload);
+        let scope = self.scope(&date);
+        let string_to_sign = string_to_sign(&timestamp, &scope, &canonical_request);
+
+        let key = [date.as_str(), &self.region, &self.service, "aws4_request"]
+            .iter()
+            .try_fold(
+                format!("AWS4{}", credentials.secret_access_key).into_bytes(),
+                |key, part| hmac(&key, part.as_bytes()),
+            )?;
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes())?);
+
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!(
+                "{ALGORITHM} Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                credentials.access_key_id
+            ))?,
+        );
+        Ok(())
+    }
+
+    // scope is the date, region and service the signature is good for
+    fn scope(&self, date: &str) -> String {
+        format!("{date}/{}/{}/aws4_request", self.region, self.service)
+    }
+
+    // canonical_request returns the canonical form of the request, along with the list of
+    // headers that were signed
+    fn canonical_request(&self, request: &Request, payload: &str) -> (String, String) {
+        let url = request.url();
+
+        // every service other than s3 expects the path to be encoded a second time
+        let path = url
+            .path()
+            .split('/')
+            .map(|segment| {
+                let segment = encode(&percent_decode_str(segment).decode_utf8_lossy());
+                match self.service.as_str() {
+                    "s3" => segment,
+                    _ => encode(&segment),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("/");
+
+        let mut query: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| (encode(&name), encode(&value)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        // the host header is added when the request is sent, but it must be signed
+        let mut headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+        headers.insert("host".to_owned(), vec![host]);
+        for (name, value) in request.headers() {
+            // the user agent is often changed by proxies along the way
+            if name == USER_AGENT {
+                continue;
+            }
+            let value = String::from_utf8_lossy(value.as_bytes());
+            headers
+                .entry(name.as_str().to_owned())
+                .or_default()
+                .push(value.split_whitespace().collect::<Vec<&str>>().join(" "));
+        }
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, values)| format!("{name}:{}\n", values.join(",")))
+            .collect();
+        let signed_headers = headers.keys().cloned().collect::<Vec<String>>().join(";");
+
+        (
+            format!(
+                "{}\n{path}\n{query}\n{canonical_headers}\n{signed_headers}\n{payload}",
+                request.method()
+            ),
+            signed_headers,
+        )
+    }
+}
+
+impl AwsCredentials {
+    // load reads the credentials from the environment variables, falling back to the shared
+    // credentials file, which can be moved with AWS_SHARED_CREDENTIALS_FILE.
+    pub fn load() -> Result<AwsCredentials, Error> {
+        if let (Ok(access_key_id), Ok(secret_access_key)) = (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            return Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            });
+        }
+
+        let path = match env::var("AWS_SHARED_CREDENTIALS_FILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => PathBuf::from(env::var("HOME").unwrap_or_default())
+                .join(".aws")
+                .join("credentials"),
+        };
+        let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_owned());
+        let missing = || {
+            Error::ConfigError(format!(
+                "there are no AWS credentials, set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY or add the profile {profile} to {}",
+                path.display()
+            ))
+        };
+
+        let content = fs::read_to_string(&path).map_err(|_| missing())?;
+        let values = profile_values(&content, &profile);
+        Ok(AwsCredentials {
+            access_key_id: values
+                .get("aws_access_key_id")
+                .cloned()
+                .ok_or_else(missing)?,
+            secret_access_key: values
+                .get("aws_secret_access_key")
+                .cloned()
+                .ok_or_else(missing)?,
+            session_token: values.get("aws_session_token").cloned(),
+        })
+    }
+}
+
+// profile_values returns the keys and values under `[profile]` in the ini formatted
+// credentials file
+fn profile_values(content: &str, profile: &str) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    let mut current = false;
+    for line in content.lines().map(|line| line.trim()) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = section.trim() == profile;
+            continue;
+        }
+        if let (true, Some((key, value))) = (current, line.split_once('=')) {
+            values.insert(key.trim().to_lowercase(), value.trim().to_owned());
+        }
+    }
+    values
+}
+
+fn string_to_sign(timestamp: &str, scope: &str, canonical_request: &str) -> String {
+    format!(
+        "{ALGORITHM}\n{timestamp}\n{scope}\n{}",
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    )
+}
+
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, UNRESERVED).to_string()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|err| Error::InvalidArguments(err.to_string()))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+// timestamp returns the date (20150830) and time (20150830T123600Z) in UTC, as they are
+// used in the signature
+fn timestamp(time: SystemTime) -> (String, String) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, rest) = (secs / 86400, secs % 86400);
+
+    // converts the days since the epoch into a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = format!("{year:04}{month:02}{day:02}");
+    let time = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    );
+    (date, time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::{Body, Client, Method};
+    use std::time::Duration;
+
+    // the examples from the aws-sig-v4-test-suite, which all sign as this key at this time
+    // for the service `service` in us-east-1
+    const TIME: u64 = 1440938160;
+
+    fn credentials() -> AwsCredentials {
+        AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_owned(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            session_token: None,
+        }
+    }
+
+    fn request(method: Method, path: &str) -> Request {
+        Client::new()
+            .request(method, format!("https://example.amazonaws.com{path}"))
+            .build()
+            .unwrap()
+    }
+
+    fn sign(request: &mut Request) {
+        AwsSigV4::parse("service:us-east-1")
+            .unwrap()
+            .sign(
+                request,
+                &credentials(),
+                UNIX_EPOCH + Duration::from_secs(TIME),
+            )
+            .unwrap();
+    }
+
+    fn signature(mut request: Request) -> String {
+        sign(&mut request);
+        let authorization = request.headers()[AUTHORIZATION].to_str().unwrap();
+        authorization
+            .rsplit_once("Signature=")
+            .unwrap()
+            .1
+            .to_owned()
+    }
+
+    #[test]
+    fn get_vanilla() {
+        let mut request = request(Method::GET, "/");
+        sign(&mut request);
+        // the canonical request is made from the headers as they were before signing
+        let authorization = request.headers_mut().remove(AUTHORIZATION).unwrap();
+
+        let sigv4 = AwsSigV4::parse("service:us-east-1").unwrap();
+        let (canonical_request, signed_headers) = sigv4.canonical_request(
+            &request,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        );
+        assert_eq!(
+            canonical_request,
+            "GET\n/\n\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\nhost;x-amz-date\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(signed_headers, "host;x-amz-date");
+        assert_eq!(
+            string_to_sign(
+                "20150830T123600Z",
+                &sigv4.scope("20150830"),
+                &canonical_request
+            ),
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\nbb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63"
+        );
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_query_order_key_case() {
+        assert_eq!(
+            signature(request(Method::GET, "/?Param2=value2&Param1=value1")),
+            "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_query_order_key() {
+        assert_eq!(
+            signature(request(Method::GET, "/?Param1=value2&Param1=Value1")),
+            "eedbc4e291e521cf13422ffca22be7d2eb8146eecf653089df300a15b2382bd1"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_query_unreserved() {
+        let unreserved = "-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+        assert_eq!(
+            signature(request(
+                Method::GET,
+                &format!("/?{unreserved}={unreserved}")
+            )),
+            "9c3e54bfcdf0b19771a7f523ee5669cdf59bc7cc0884027167c21bb143a40197"
+        );
+    }
+
+    #[test]
+    fn get_vanilla_utf8_query() {
+        assert_eq!(
+            signature(request(Method::GET, "/?ሴ=bar")),
+            "2cdec8eed098649ff3a119c94853b13c643bcf08f8b0a1d91e12c9027818dd04"
+        );
+    }
+
+    // the suite encodes the path of get-space once, while every service other than s3
+    // expects it to be encoded twice, so the canonical path is checked rather than the
+    // suite's signature
+    #[test]
+    fn get_space() {
+        let request = request(Method::GET, "/example space/");
+        let sigv4 = AwsSigV4::parse("service:us-east-1").unwrap();
+        let (canonical_request, _) = sigv4.canonical_request(&request, "");
+        assert_eq!(
+            canonical_request.lines().nth(1),
+            Some("/example%2520space/")
+        );
+
+        let sigv4 = AwsSigV4::parse("s3:us-east-1").unwrap();
+        let (canonical_request, _) = sigv4.canonical_request(&request, "");
+        assert_eq!(canonical_request.lines().nth(1), Some("/example%20space/"));
+    }
+
+    #[test]
+    fn post_vanilla() {
+        assert_eq!(
+            signature(request(Method::POST, "/")),
+            "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b"
+        );
+    }
+
+    #[test]
+    fn post_x_www_form_urlencoded() {
+        let request = Client::new()
+            .post("https://example.amazonaws.com/")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("Param1=value1")
+            .build()
+            .unwrap();
+        assert_eq!(
+            signature(request),
+            "ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+    }
+
+    #[test]
+    fn stream_bodies_are_rejected() {
+        let stream = futures::stream::iter(vec![Ok::<_, std::io::Error>("body")]);
+        let mut request = Client::new()
+            .post("https://example.amazonaws.com/")
+            .body(Body::wrap_stream(stream))
+            .build()
+            .unwrap();
+
+        let result = AwsSigV4::parse("service:us-east-1").unwrap().sign(
+            &mut request,
+            &credentials(),
+            UNIX_EPOCH + Duration::from_secs(TIME),
+        );
+        assert!(result.is_err());
+
+        AwsSigV4::parse("s3:us-east-1")
+            .unwrap()
+            .sign(
+                &mut request,
+                &credentials(),
+                UNIX_EPOCH + Duration::from_secs(TIME),
+            )
+            .unwrap();
+        assert_eq!(request.headers()["x-amz-content-sha256"], UNSIGNED_PAYLOAD);
+    }
+
+    #[test]
+    fn timestamps() {
+        let at = |secs| timestamp(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(
+            at(TIME),
+            ("20150830".to_owned(), "20150830T123600Z".to_owned())
+        );
+        assert_eq!(
+            at(0),
+            ("19700101".to_owned(), "19700101T000000Z".to_owned())
+        );
+        assert_eq!(
+            at(951868799),
+            ("20000229".to_owned(), "20000229T235959Z".to_owned())
+        );
+        assert_eq!(
+            at(1735603200),
+            ("20241231".to_owned(), "20241231T000000Z".to_owned())
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        let content = "\
+# the default profile
+[default]
+aws_access_key_id = AKIDDEFAULT
+aws_secret_access_key = default/secret
+
+[work]
+; temporary credentials
+AWS_ACCESS_KEY_ID=AKIDWORK
+aws_secret_access_key = work=secret
+aws_session_token = token
+";
+        let default = profile_values(content, "default");
+        assert_eq!(default["aws_access_key_id"], "AKIDDEFAULT");
+        assert_eq!(default["aws_secret_access_key"], "default/secret");
+        assert_eq!(default.get("aws_session_token"), None);
+
+        let work = profile_values(content, "work");
+        assert_eq!(work["aws_access_key_id"], "AKIDWORK");
+        assert_eq!(work["aws_secret_access_key"], "work=secret");
+        assert_eq!(work["aws_session_token"], "token");
+
+        assert!(profile_values(content, "missing").is_empty());
+    }
+}
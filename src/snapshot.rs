@@ -0,0 +1,162 @@
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fs, path::Path};
+use tera::Context;
+
+// the value left in place of anything matched by an ignored pointer
+const IGNORED: &str = "<ignored>";
+
+// Snapshot is a recorded response that later responses are compared against. Only the
+// status, the headers that were asked for and the body are kept. Volatile fields such as
+// timestamps are listed as JSON pointers under ignore, where `*` matches every item in an
+// array or object.
+//
+//   {
+//     "status": 200,
+//     "headers": { "content-type": "application/json" },
+//     "body": { "id": 42, "created_at": "<ignored>" },
+//     "ignore": ["/created_at"]
+//   }
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub body: Value,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+}
+
+impl Snapshot {
+    // from_response builds a snapshot from the context built by Template::send, keeping the
+    // given headers and blanking out the ignored fields of the body.
+    pub fn from_response(response: &Context, headers: &[String], ignore: &[String]) -> Snapshot {
+        let status = response
+            .get("resp_status")
+            .and_then(|status| status.as_str())
+            .and_then(|status| status.parse().ok())
+            .unwrap_or_default();
+
+        let headers = headers
+            .iter()
+            .filter_map(|name| {
+                let name = name.to_lowercase();
+                let value = response.get(&format!("resp_headers_{name}"))?.as_str()?;
+                Some((name, value.to_owned()))
+            })
+            .collect();
+
+        let content = response
+            .get("resp_body")
+            .and_then(|body| body.as_str())
+            .unwrap_or_default();
+        let mut body =
+            serde_json::from_str(content).unwrap_or_else(|_| Value::String(content.to_owned()));
+        for pointer in ignore {
+            blank(
+                &mut body,
+                &pointer.split('/').skip(1).collect::<Vec<&str>>(),
+            );
+        }
+
+        Snapshot {
+            status,
+            headers,
+            body,
+            ignore: ignore.to_vec(),
+        }
+    }
+
+    // load reads the snapshot at path, returning None when it has not been recorded yet
+    pub fn load(path: &str) -> Result<Option<Snapshot>, Error> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut content = serde_json::to_string_pretty(self)?;
+        content.push('\n');
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    // diff lists every difference between the snapshot and the actual response, one line
+    // per value that changed, was added or was removed.
+    pub fn diff(&self, actual: &Snapshot) -> Vec<String> {
+        let mut changes = Vec::new();
+        diff(
+            "/status",
+            Some(&Value::from(self.status)),
+            Some(&Value::from(actual.status)),
+            &mut changes,
+        );
+        diff(
+            "/headers",
+            Some(&serde_json::to_value(&self.headers).unwrap_or_default()),
+            Some(&serde_json::to_value(&actual.headers).unwrap_or_default()),
+            &mut changes,
+        );
+        diff("/body", Some(&self.body), Some(&actual.body), &mut changes);
+        changes
+    }
+}
+
+// blank replaces the values matched by the pointer, split into its parts, with IGNORED
+fn blank(value: &mut Value, pointer: &[&str]) {
+    let Some((part, rest)) = pointer.split_first() else {
+        if !value.is_null() {
+            *value = Value::String(IGNORED.to_owned());
+        }
+        return;
+    };
+    let part = part.replace("~1", "/").replace("~0", "~");
+
+    match value {
+        Value::Object(map) if part == "*" => map.values_mut().for_each(|v| blank(v, rest)),
+        Value::Array(items) if part == "*" => items.iter_mut().for_each(|v| blank(v, rest)),
+        Value::Object(map) => {
+            if let Some(v) = map.get_mut(&part) {
+                blank(v, rest)
+            }
+        }
+        Value::Array(items) => {
+            if let Some(v) = part.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                blank(v, rest)
+            }
+        }
+        _ => (),
+    }
+}
+
+fn diff(path: &str, expected: Option<&Value>, actual: Option<&Value>, changes: &mut Vec<String>) {
+    match (expected, actual) {
+        (Some(Value::Object(expected)), Some(Value::Object(actual))) => {
+            let mut keys: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let child = format!("{path}/{}", key.replace('~', "~0").replace('/', "~1"));
+                diff(&child, expected.get(key), actual.get(key), changes);
+            }
+        }
+        (Some(Value::Array(expected)), Some(Value::Array(actual))) => {
+            for i in 0..expected.len().max(actual.len()) {
+                diff(
+                    &format!("{path}/{i}"),
+                    expected.get(i),
+                    actual.get(i),
+                    changes,
+                );
+            }
+        }
+        (Some(expected), Some(actual)) if expected != actual => {
+            changes.push(format!("~ {path}: {expected} -> {actual}"))
+        }
+        (Some(expected), None) => changes.push(format!("- {path}: {expected}")),
+        (None, Some(actual)) => changes.push(format!("+ {path}: {actual}")),
+        _ => (),
+    }
+}
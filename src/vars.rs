@@ -0,0 +1,51 @@
+use crate::{secret, Environment, Error};
+use std::collections::HashMap;
+use tera::{Context, Tera};
+
+// variables builds the values available when rendering a request. Process environment
+// variables live under `env` (eg `{{ env.HOME }}`), the vars from the environment are laid
+// on top of that, and finally the `--var key=value` arguments from the command line.
+pub fn variables<'a, T>(env: &Environment, vars: Option<T>) -> Result<Context, Error>
+where
+    T: Iterator<Item = &'a String>,
+{
+    let mut context = Context::new();
+    context.insert(
+        "env",
+        &std::env::vars().collect::<HashMap<String, String>>(),
+    );
+
+    // vars are inserted as values rather than rendered, so a secret is never parsed by tera
+    for (name, value) in env.vars.iter() {
+        context.insert(
+            name,
+            &secret::resolve_json(value, &env.key(&format!("vars.{name}")))?,
+        );
+    }
+
+    for var in vars.into_iter().flatten() {
+        let (name, value) = var.split_once('=').ok_or(Error::InvalidArguments(format!(
+            "{var} is not a valid key=value"
+        )))?;
+        context.insert(name.trim(), value.trim());
+    }
+
+    Ok(context)
+}
+
+// render runs the value through tera. Autoescaping is disabled since we are building http
+// requests, not html.
+pub fn render(value: &str, context: &Context) -> Result<String, Error> {
+    Ok(Tera::one_off(value, context, false)?)
+}
+
+// render_all renders each of the values, keeping the shape of the clap arguments so the
+// result can be handed straight to the `opt_*` functions.
+pub fn render_all<'a, T>(values: Option<T>, context: &Context) -> Result<Option<Vec<String>>, Error>
+where
+    T: Iterator<Item = &'a String>,
+{
+    values
+        .map(|values| values.map(|v| render(v, context)).collect())
+        .transpose()
+}
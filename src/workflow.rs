@@ -0,0 +1,80 @@
+use crate::{render, Error, ParamType, RequestTemplate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tera::Context;
+
+// Workflow is a named sequence of templates saved under an environment. Each step runs a
+// template, and can capture values out of the response for the steps that follow. Captures
+// are tera templates rendered against the response, the same values that are available when
+// formatting the output.
+//
+//   [environment.local.workflow.create-user]
+//   description = "log in, then create and fetch a user"
+//
+//   [[environment.local.workflow.create-user.steps]]
+//   template = "login"
+//   capture = { token = "{{ access_token }}" }
+//
+//   [[environment.local.workflow.create-user.steps]]
+//   template = "create-user"
+//   params = { name = "jed" }
+//   capture = { id = "{{ id }}" }
+//
+//   [[environment.local.workflow.create-user.steps]]
+//   template = "get-user"
+//   params = { id = "{{ id }}" }
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Workflow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Step {
+    pub template: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub capture: BTreeMap<String, String>,
+}
+
+impl Step {
+    // context renders the params of the step and passes them to the template as if they
+    // had been given on the command line, so they are validated the same way.
+    pub fn context(&self, template: &RequestTemplate, context: Context) -> Result<Context, Error> {
+        let mut args = Vec::new();
+        for (name, value) in self.params.iter() {
+            let value = render(value, &context)?;
+            let boolean = template
+                .params
+                .iter()
+                .any(|param| &param.name == name && param.kind == ParamType::Boolean);
+
+            match (boolean, value.as_str()) {
+                (true, "true") => args.push(format!("--{name}")),
+                (true, _) => (),
+                (false, value) => args.push(format!("--{name}={value}")),
+            }
+        }
+
+        let matches = template
+            .command(&self.template)
+            .try_get_matches_from(args)
+            .map_err(|err| {
+                Error::InvalidArguments(format!("step {}: {}", self.template, err.render()))
+            })?;
+
+        Ok(template.context(&matches, context))
+    }
+
+    // capture renders each of the captures against the response, adding the results to the
+    // context used by the steps that follow.
+    pub fn capture(&self, response: &Context, context: &mut Context) -> Result<(), Error> {
+        for (name, value) in self.capture.iter() {
+            context.insert(name, &render(value, response)?);
+        }
+        Ok(())
+    }
+}